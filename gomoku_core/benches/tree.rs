@@ -0,0 +1,148 @@
+//! Compares expanding a search tree whose children are boxed slices owned by
+//! their parent, with one whose children live in a shared `Arena` and are
+//! referenced by index.
+
+use std::sync::atomic::{AtomicI32, AtomicU32, AtomicU64, Ordering::*};
+use std::sync::OnceLock;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use gomoku_core::mcts::arena::{Arena, CHUNK_SIZE};
+
+// Roughly the layout of a search tree node, apart from how children are
+// referenced.
+struct BoxedNode {
+    visits: AtomicU32,
+    score: AtomicI32,
+    children: OnceLock<Box<[BoxedNode]>>,
+}
+
+impl BoxedNode {
+    fn new() -> Self {
+        Self {
+            visits: AtomicU32::new(0),
+            score: AtomicI32::new(0),
+            children: OnceLock::new(),
+        }
+    }
+}
+
+struct ArenaNode {
+    visits: AtomicU32,
+    score: AtomicI32,
+    children: AtomicU64,
+}
+
+const NOT_EXPANDED: u64 = u64::MAX;
+
+impl ArenaNode {
+    fn new() -> Self {
+        Self {
+            visits: AtomicU32::new(0),
+            score: AtomicI32::new(0),
+            children: AtomicU64::new(NOT_EXPANDED),
+        }
+    }
+
+    fn children<'a>(&self, arena: &'a Arena<ArenaNode>) -> Option<&'a [ArenaNode]> {
+        match self.children.load(Acquire) {
+            NOT_EXPANDED => None,
+            packed => Some(arena.get((packed >> 16) as usize, (packed & 0xffff) as usize)),
+        }
+    }
+
+    fn expand<'a>(&self, arena: &'a Arena<ArenaNode>, len: usize) -> &'a [ArenaNode] {
+        let start = arena.alloc((0..len).map(|_| ArenaNode::new()));
+        self.children
+            .store((start as u64) << 16 | len as u64, Release);
+        arena.get(start, len)
+    }
+}
+
+// Descend along a pseudo random path, and expand the first unexpanded node,
+// the way a simulation grows the tree.
+fn path(i: usize, depth: usize) -> usize {
+    (i.wrapping_mul(0x9E37_79B9) >> depth) % 7
+}
+
+fn grow_boxed(root: &BoxedNode, expansions: usize) {
+    for i in 0..expansions {
+        let mut node = root;
+        let mut depth = 0;
+        while let Some(children) = node.children.get() {
+            node.visits.fetch_add(1, Relaxed);
+            node.score.fetch_add(1, Relaxed);
+            node = &children[path(i, depth) % children.len()];
+            depth += 1;
+        }
+        node.children
+            .get_or_init(|| (0..225 - depth).map(|_| BoxedNode::new()).collect());
+    }
+}
+
+fn grow_arena(root: &ArenaNode, arena: &Arena<ArenaNode>, expansions: usize) {
+    for i in 0..expansions {
+        let mut node = root;
+        let mut depth = 0;
+        while let Some(children) = node.children(arena) {
+            node.visits.fetch_add(1, Relaxed);
+            node.score.fetch_add(1, Relaxed);
+            node = &children[path(i, depth) % children.len()];
+            depth += 1;
+        }
+        node.expand(arena, 225 - depth);
+    }
+}
+
+fn count_boxed(node: &BoxedNode) -> usize {
+    node.children.get().map_or(0, |children| {
+        children.len() + children.iter().map(count_boxed).sum::<usize>()
+    })
+}
+
+pub fn criterion_tree(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tree");
+    for expansions in [100, 1000, 10000] {
+        let boxed = BoxedNode::new();
+        grow_boxed(&boxed, expansions);
+        let arena = Arena::new();
+        let root = ArenaNode::new();
+        grow_arena(&root, &arena, expansions);
+        // Each boxed slice also pays for its allocation header.
+        let nodes = count_boxed(&boxed);
+        println!(
+            "{expansions} expansions, {nodes} nodes: boxed ~{} KiB in {expansions} allocations, \
+             arena {} KiB in {} allocations",
+            (nodes * std::mem::size_of::<BoxedNode>() + expansions * 16) / 1024,
+            arena.memory() / 1024,
+            arena.memory() / (CHUNK_SIZE * std::mem::size_of::<ArenaNode>()),
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("boxed", expansions),
+            &expansions,
+            |b, &n| {
+                b.iter(|| {
+                    let root = BoxedNode::new();
+                    grow_boxed(&root, n);
+                    root
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("arena", expansions),
+            &expansions,
+            |b, &n| {
+                b.iter(|| {
+                    let arena = Arena::new();
+                    let root = ArenaNode::new();
+                    grow_arena(&root, &arena, n);
+                    (root, arena)
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, criterion_tree);
+criterion_main!(benches);
@@ -0,0 +1,268 @@
+//! An opening book mapping positions to move statistics.
+//!
+//! Positions are stored under their rule and canonical hash, so a book entry
+//! is shared by all 8 symmetric variants of a position, and moves are stored
+//! in the orientation of the canonical position.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::Ordering::*;
+
+use rand::{distributions::WeightedIndex, prelude::Distribution, Rng};
+
+use crate::gomoku::{Gomoku, Move, Rule, State, Stone};
+use crate::interface::Game;
+use crate::io_util::{invalid_data, read_array, read_rule, rule_byte};
+use crate::mcts::{persistence, tree::Tree};
+
+const MAGIC: &[u8; 4] = b"BOOK";
+const VERSION: u32 = 2;
+
+/// Statistics of a move in the book.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BookMove {
+    pub m: Move,
+    pub visits: u32,
+    /// +1 for wins, -1 for losses, +0 for draws.
+    /// From perspective of the player making the move.
+    pub score: i64,
+}
+
+impl BookMove {
+    /// Normalized so all wins is 1.0, all draws is 0.5, and all losses is 0.0.
+    pub fn win_rate(&self) -> f64 {
+        if self.visits == 0 {
+            return 0.5;
+        }
+        (self.score as f64 + self.visits as f64) / (self.visits as f64 * 2.0)
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct OpeningBook {
+    positions: HashMap<(Rule, u64), Vec<BookMove>>,
+}
+
+impl OpeningBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A book that only knows to open in the center of the board.
+    pub fn center_opening() -> Self {
+        let mut book = Self::new();
+        for rule in [Rule::Freestyle, Rule::Standard] {
+            book.add(&State::default().with_rule(rule), Move::new(7, 7), 1, 0);
+        }
+        book
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Merge the statistics of playing `m` at `state` into the book.
+    pub fn add(&mut self, state: &State, m: Move, visits: u32, score: i64) {
+        let (hash, symmetry) = state.canonical_hash();
+        let m = m.transform(symmetry);
+        let moves = self.positions.entry((state.rule, hash)).or_default();
+        match moves.iter_mut().find(|book_move| book_move.m == m) {
+            Some(book_move) => {
+                book_move.visits = book_move.visits.saturating_add(visits);
+                book_move.score = book_move.score.saturating_add(score);
+            }
+            None => moves.push(BookMove { m, visits, score }),
+        }
+    }
+
+    /// Add the first `max_ply` moves of a finished game.
+    /// `winner` is None for a draw.
+    pub fn add_game(&mut self, game: &State, winner: Option<Stone>, max_ply: usize) {
+        let mut state = State::default().with_rule(game.rule);
+        for m in game.pieces.iter().take(max_ply) {
+            let score = match winner {
+                Some(stone) if stone == state.player_to_move() => 1,
+                Some(_) => -1,
+                None => 0,
+            };
+            self.add(&state, *m, 1, score);
+            Gomoku::apply(&mut state, m);
+        }
+    }
+
+    /// Add every move of the first `max_ply` moves of a search tree that was
+    /// visited at least `min_visits` times.
+    pub(crate) fn add_tree(&mut self, tree: &Tree, min_visits: u32, max_ply: usize) {
+        tree.root.for_each_edge(
+            &tree.arena,
+            &mut State::default().with_rule(tree.rule()),
+            max_ply,
+            min_visits,
+            &mut |state, child| {
+                let visits = child.visits.load(Relaxed);
+                let score = child.score.load(Relaxed) as i64;
+                self.add(state, child.m.unwrap(), visits, score);
+            },
+        );
+    }
+
+    /// Merge a search tree saved by `MonteCarloTreeSearchContinue::save_tree`.
+    pub fn add_tree_file(
+        &mut self,
+        path: impl AsRef<Path>,
+        min_visits: u32,
+        max_ply: usize,
+    ) -> io::Result<()> {
+        let tree = persistence::load_tree(BufReader::new(File::open(path)?), None)?;
+        self.add_tree(&tree, min_visits, max_ply);
+        Ok(())
+    }
+
+    /// Merge another book into this one.
+    pub fn merge(&mut self, other: &OpeningBook) {
+        for (key, moves) in other.positions.iter() {
+            let entry = self.positions.entry(*key).or_default();
+            for book_move in moves {
+                match entry.iter_mut().find(|m| m.m == book_move.m) {
+                    Some(m) => {
+                        m.visits = m.visits.saturating_add(book_move.visits);
+                        m.score = m.score.saturating_add(book_move.score);
+                    }
+                    None => entry.push(*book_move),
+                }
+            }
+        }
+    }
+
+    /// The book moves of `state`, in its own orientation.
+    pub fn lookup(&self, state: &State) -> Vec<BookMove> {
+        let (hash, symmetry) = state.canonical_hash();
+        let Some(moves) = self.positions.get(&(state.rule, hash)) else {
+            return Vec::new();
+        };
+        let inverse = symmetry.inverse();
+        moves
+            .iter()
+            .map(|book_move| BookMove {
+                m: book_move.m.transform(inverse),
+                ..*book_move
+            })
+            .filter(|book_move| !state.pieces.contains(&book_move.m))
+            .collect()
+    }
+
+    /// Pick a book move of `state` at random, weighted by visits, among the
+    /// moves that didn't lose more often than they won.
+    pub fn choose_move<R: Rng>(&self, state: &State, rng: &mut R) -> Option<Move> {
+        let mut moves = self.lookup(state);
+        moves.retain(|book_move| book_move.score >= 0);
+        let dist = WeightedIndex::new(moves.iter().map(|m| m.visits)).ok()?;
+        Some(moves[dist.sample(rng)].m)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(self.positions.len() as u64).to_le_bytes())?;
+        for ((rule, hash), moves) in self.positions.iter() {
+            writer.write_all(&[rule_byte(*rule)])?;
+            writer.write_all(&hash.to_le_bytes())?;
+            writer.write_all(&(moves.len() as u16).to_le_bytes())?;
+            for book_move in moves {
+                writer.write_all(&[book_move.m.index()])?;
+                writer.write_all(&book_move.visits.to_le_bytes())?;
+                writer.write_all(&book_move.score.to_le_bytes())?;
+            }
+        }
+        writer.flush()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        if &read_array(&mut reader)? != MAGIC {
+            return Err(invalid_data("not an opening book file"));
+        }
+        // Books before version 2 were all freestyle, without the rule.
+        let version = u32::from_le_bytes(read_array(&mut reader)?);
+        if version != 1 && version != VERSION {
+            return Err(invalid_data("unsupported opening book version"));
+        }
+
+        let mut book = Self::new();
+        let num_positions = u64::from_le_bytes(read_array(&mut reader)?);
+        for _ in 0..num_positions {
+            let rule = match version {
+                1 => Rule::Freestyle,
+                _ => read_rule(&mut reader)?,
+            };
+            let hash = u64::from_le_bytes(read_array(&mut reader)?);
+            let num_moves = u16::from_le_bytes(read_array(&mut reader)?);
+            let mut moves = Vec::with_capacity(num_moves as usize);
+            for _ in 0..num_moves {
+                let [index] = read_array(&mut reader)?;
+                if index >= 225 {
+                    return Err(invalid_data("illegal move in opening book"));
+                }
+                moves.push(BookMove {
+                    m: Move::new(index as usize / 15, index as usize % 15),
+                    visits: u32::from_le_bytes(read_array(&mut reader)?),
+                    score: i64::from_le_bytes(read_array(&mut reader)?),
+                });
+            }
+            book.positions.insert((rule, hash), moves);
+        }
+        Ok(book)
+    }
+}
+
+#[test]
+fn test_symmetric_lookup() {
+    let mut book = OpeningBook::new();
+    let game = State::new(vec![Move::new(7, 7), Move::new(6, 8), Move::new(5, 9)]);
+    book.add_game(&game, Some(Stone::Black), 3);
+    book.add_game(&game, Some(Stone::White), 3);
+
+    // The same opening, mirrored.
+    let state = State::new(vec![Move::new(7, 7), Move::new(6, 6)]);
+    assert_eq!(
+        book.lookup(&state),
+        vec![BookMove {
+            m: Move::new(5, 5),
+            visits: 2,
+            score: 0,
+        }]
+    );
+    assert_eq!(
+        book.lookup(&State::new(vec![Move::new(7, 7), Move::new(7, 8)])),
+        vec![]
+    );
+    // Nor does it know the opening under the other rule.
+    assert_eq!(book.lookup(&state.with_rule(Rule::Standard)), vec![]);
+    assert_eq!(
+        book.choose_move(&State::default(), &mut rand::thread_rng()),
+        Some(Move::new(7, 7))
+    );
+
+    // Merging large counts saturates instead of overflowing.
+    let mut big = OpeningBook::new();
+    big.add(&State::default(), Move::new(7, 7), u32::MAX, 0);
+    big.merge(&big.clone());
+    assert_eq!(big.lookup(&State::default())[0].visits, u32::MAX);
+
+    // The losing reply is never played.
+    let lost = State::new(vec![Move::new(7, 7), Move::new(0, 0), Move::new(7, 8)]);
+    book.add_game(&lost, Some(Stone::Black), 3);
+    let state = State::new(vec![Move::new(7, 7)]);
+    assert_eq!(book.lookup(&state).len(), 2);
+    for _ in 0..20 {
+        let m = book.choose_move(&state, &mut rand::thread_rng());
+        assert_eq!(m, Some(Move::new(6, 8)));
+    }
+}
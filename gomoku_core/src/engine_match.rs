@@ -0,0 +1,428 @@
+//! Matches between two engines, to compare their strength.
+//!
+//! Games are played in pairs from the same opening, each engine playing
+//! black once, so neither profits from a lucky opening. The result is reported
+//! from the perspective of the first engine, `A`, as wins, draws and losses,
+//! and as an Elo difference with a 95% confidence interval.
+//!
+//! With a sequential probability ratio test (SPRT), the match stops as soon as
+//! the results are significant enough to tell whether `A` is at least `elo1`
+//! stronger than `B`, or at most `elo0`.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::game_result::{GameResult, Termination};
+use crate::gomoku::{Gomoku, Move, State, Stone};
+use crate::interface::{Game, Strategy};
+use crate::record::{self, GameInfo};
+
+/// A sequential probability ratio test between two hypotheses: `A` is `elo0`
+/// stronger than `B`, or `elo1` stronger.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    /// Probability of accepting `elo1` when `elo0` is true.
+    pub alpha: f64,
+    /// Probability of accepting `elo0` when `elo1` is true.
+    pub beta: f64,
+}
+
+impl Sprt {
+    /// A test with 5% error probabilities.
+    pub fn new(elo0: f64, elo1: f64) -> Self {
+        Self {
+            elo0,
+            elo1,
+            alpha: 0.05,
+            beta: 0.05,
+        }
+    }
+
+    // Below this log likelihood ratio, elo0 is accepted.
+    fn lower_bound(&self) -> f64 {
+        (self.beta / (1.0 - self.alpha)).ln()
+    }
+
+    // Above this log likelihood ratio, elo1 is accepted.
+    fn upper_bound(&self) -> f64 {
+        ((1.0 - self.beta) / self.alpha).ln()
+    }
+}
+
+/// The hypothesis accepted by an SPRT.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SprtResult {
+    /// `A` is at most `elo0` stronger.
+    AcceptElo0,
+    /// `A` is at least `elo1` stronger.
+    AcceptElo1,
+}
+
+/// Options of a match.
+#[derive(Clone, Debug)]
+pub struct MatchOptions {
+    games: u32,
+    opening_moves: usize,
+    openings: Vec<State>,
+    // None means seeded from the OS.
+    seed: Option<u64>,
+    sprt: Option<Sprt>,
+    record: Option<PathBuf>,
+    names: (String, String),
+    draw_after: usize,
+    // None means engines can take as long as they like.
+    time_limit: Option<Duration>,
+}
+
+impl MatchOptions {
+    /// A match of at most `games` games.
+    pub fn new(games: u32) -> Self {
+        Self {
+            games,
+            opening_moves: 3,
+            openings: Vec::new(),
+            seed: None,
+            sprt: None,
+            record: None,
+            names: ("A".to_string(), "B".to_string()),
+            draw_after: 200,
+            time_limit: None,
+        }
+    }
+
+    /// Start each pair of games with this many random moves near the center.
+    /// Defaults to 3.
+    pub fn with_opening_moves(mut self, moves: usize) -> Self {
+        self.opening_moves = moves;
+        self
+    }
+
+    /// Start the games from these positions, in turn, instead of random ones.
+    pub fn with_openings(mut self, openings: Vec<State>) -> Self {
+        self.openings = openings;
+        self
+    }
+
+    /// Seed the generation of random openings.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Stop the match early once the SPRT accepts one of its hypotheses.
+    pub fn with_sprt(mut self, sprt: Sprt) -> Self {
+        self.sprt = Some(sprt);
+        self
+    }
+
+    /// Write every game to this file, as an SGF collection.
+    pub fn with_record(mut self, path: impl Into<PathBuf>) -> Self {
+        self.record = Some(path.into());
+        self
+    }
+
+    /// Names of the engines in the records. Defaults to `A` and `B`.
+    pub fn with_names(mut self, a: impl Into<String>, b: impl Into<String>) -> Self {
+        self.names = (a.into(), b.into());
+        self
+    }
+
+    /// Adjudicate games as draws after this many moves, see
+    /// `GameResult::adjudicate`. Defaults to 200.
+    pub fn with_draw_adjudication(mut self, moves: usize) -> Self {
+        self.draw_after = moves;
+        self
+    }
+
+    /// An engine taking longer than this to choose a move loses on time.
+    /// Defaults to no limit.
+    pub fn with_time_limit(mut self, limit: Duration) -> Self {
+        self.time_limit = Some(limit);
+        self
+    }
+}
+
+/// A finished game of a match.
+#[derive(Clone, Debug)]
+pub struct MatchGame {
+    pub state: State,
+    /// Whether `A` played black.
+    pub a_is_black: bool,
+    pub result: GameResult,
+}
+
+impl MatchGame {
+    /// Whether `A` won, None for a draw.
+    pub fn a_won(&self) -> Option<bool> {
+        let a = if self.a_is_black {
+            Stone::Black
+        } else {
+            Stone::White
+        };
+        self.result.winner.map(|winner| winner == a)
+    }
+}
+
+/// An Elo difference with the half width of its 95% confidence interval.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Elo {
+    pub diff: f64,
+    pub margin: f64,
+}
+
+/// Results of a match, from the perspective of `A`.
+#[derive(Clone, Debug, Default)]
+pub struct MatchResult {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+    /// Set if the match was stopped by the SPRT.
+    pub sprt: Option<SprtResult>,
+}
+
+// The Elo difference expected to give this score.
+fn elo_from_score(score: f64) -> f64 {
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+fn score_from_elo(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+impl MatchResult {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    /// The average score of `A`, counting draws as half a win.
+    pub fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games().max(1) as f64
+    }
+
+    // Mean and variance of the score of a game.
+    fn score_variance(&self) -> (f64, f64) {
+        let n = self.games() as f64;
+        let mean = self.score();
+        let variance = (self.wins as f64 * (1.0 - mean).powi(2)
+            + self.draws as f64 * (0.5 - mean).powi(2)
+            + self.losses as f64 * mean.powi(2))
+            / n;
+        (mean, variance)
+    }
+
+    /// The Elo difference between `A` and `B`. None until both engines
+    /// scored at least once, as it is infinite otherwise.
+    pub fn elo(&self) -> Option<Elo> {
+        let (mean, variance) = self.score_variance();
+        if self.games() == 0 || mean <= 0.0 || mean >= 1.0 {
+            return None;
+        }
+        let deviation = 1.96 * (variance / self.games() as f64).sqrt();
+        let lower = elo_from_score((mean - deviation).max(f64::EPSILON));
+        let upper = elo_from_score((mean + deviation).min(1.0 - f64::EPSILON));
+        Some(Elo {
+            diff: elo_from_score(mean),
+            margin: (upper - lower) / 2.0,
+        })
+    }
+
+    /// The log likelihood ratio of the hypotheses of `sprt`, with a normal
+    /// approximation of the game scores.
+    pub fn llr(&self, sprt: &Sprt) -> f64 {
+        let (mean, variance) = self.score_variance();
+        if self.games() == 0 || variance <= 0.0 {
+            return 0.0;
+        }
+        let (score0, score1) = (score_from_elo(sprt.elo0), score_from_elo(sprt.elo1));
+        self.games() as f64 * (score1 - score0) * (2.0 * mean - score0 - score1) / (2.0 * variance)
+    }
+
+    fn add(&mut self, game: &MatchGame) {
+        match game.a_won() {
+            Some(true) => self.wins += 1,
+            Some(false) => self.losses += 1,
+            None => self.draws += 1,
+        }
+    }
+}
+
+/// Play a game from `opening` until it is won, drawn or adjudicated, or a
+/// player resigns, runs out of time or fails to return a legal move.
+pub fn play_game(
+    black: &mut impl Strategy<Gomoku>,
+    white: &mut impl Strategy<Gomoku>,
+    opening: &State,
+    options: &MatchOptions,
+) -> (State, GameResult) {
+    let mut state = opening.clone();
+    loop {
+        if let Some(result) = GameResult::adjudicate(&state, options.draw_after) {
+            return (state, result);
+        }
+        let stone = state.player_to_move();
+        let player: &mut dyn Strategy<Gomoku> = match stone {
+            Stone::Black => black,
+            Stone::White => white,
+        };
+        let start = Instant::now();
+        let m = player.choose_move(&state);
+        let lose = |termination| GameResult::win(stone.opponent(), termination);
+        if options
+            .time_limit
+            .is_some_and(|limit| start.elapsed() > limit)
+        {
+            return (state, lose(Termination::Timeout));
+        }
+        if player.resigns() {
+            return (state, lose(Termination::Resignation));
+        }
+        match m {
+            Some(m) if !state.pieces.contains(&m) => Gomoku::apply(&mut state, &m),
+            _ => return (state, lose(Termination::Forfeit)),
+        }
+    }
+}
+
+// A random opening of `moves` moves in the 7x7 square at the center.
+fn random_opening(rng: &mut impl Rng, moves: usize) -> State {
+    let mut state = State::default();
+    while state.pieces.len() < moves.min(49) {
+        let m = Move::new(rng.gen_range(4..11), rng.gen_range(4..11));
+        if !state.pieces.contains(&m) {
+            Gomoku::apply(&mut state, &m);
+        }
+    }
+    state
+}
+
+/// Play a match between the engines created by `new_a` and `new_b`. Each game
+/// is played by new engines, created with the index of the game, e.g. to seed
+/// them differently. `on_game` is called after each game, with the results
+/// so far.
+pub fn run_match<A, B>(
+    mut new_a: impl FnMut(u32) -> A,
+    mut new_b: impl FnMut(u32) -> B,
+    options: &MatchOptions,
+    mut on_game: impl FnMut(&MatchGame, &MatchResult),
+) -> io::Result<MatchResult>
+where
+    A: Strategy<Gomoku>,
+    B: Strategy<Gomoku>,
+{
+    let mut rng = match options.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let mut record = match &options.record {
+        Some(path) => Some(File::create(path)?),
+        None => None,
+    };
+    let mut result = MatchResult::default();
+    let mut opening = State::default();
+    for i in 0..options.games {
+        // Both engines play each opening with both colors.
+        let a_is_black = i % 2 == 0;
+        if a_is_black {
+            opening = match options.openings.len() {
+                0 => random_opening(&mut rng, options.opening_moves),
+                n => options.openings[(i as usize / 2) % n].clone(),
+            };
+        }
+        let (mut a, mut b) = (new_a(i), new_b(i));
+        let (state, game_result) = if a_is_black {
+            play_game(&mut a, &mut b, &opening, options)
+        } else {
+            play_game(&mut b, &mut a, &opening, options)
+        };
+        let game = MatchGame {
+            state,
+            a_is_black,
+            result: game_result,
+        };
+        result.add(&game);
+
+        if let Some(file) = &mut record {
+            let (a, b) = (options.names.0.clone(), options.names.1.clone());
+            let (black, white) = if a_is_black { (a, b) } else { (b, a) };
+            let info = GameInfo {
+                black,
+                white,
+                result: game.result.to_sgf(),
+            };
+            file.write_all(record::write_sgf(&game.state, &info).as_bytes())?;
+            file.flush()?;
+        }
+
+        if let Some(sprt) = &options.sprt {
+            let llr = result.llr(sprt);
+            if llr >= sprt.upper_bound() {
+                result.sprt = Some(SprtResult::AcceptElo1);
+            } else if llr <= sprt.lower_bound() {
+                result.sprt = Some(SprtResult::AcceptElo0);
+            }
+        }
+        on_game(&game, &result);
+        if result.sprt.is_some() {
+            break;
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Plays the first empty point in reading order.
+    struct FirstEmpty;
+
+    impl Strategy<Gomoku> for FirstEmpty {
+        fn choose_move(&mut self, state: &State) -> Option<Move> {
+            (0..225)
+                .map(|i| Move::new(i / 15, i % 15))
+                .find(|m| !state.pieces.contains(m))
+        }
+    }
+
+    // Never finds a move.
+    struct NoMove;
+
+    impl Strategy<Gomoku> for NoMove {
+        fn choose_move(&mut self, _: &State) -> Option<Move> {
+            None
+        }
+    }
+
+    #[test]
+    fn test_match() {
+        let options = MatchOptions::new(4).with_seed(3).with_opening_moves(0);
+        let result = run_match(
+            |_| FirstEmpty,
+            |_| NoMove,
+            &options,
+            |game, _| assert_eq!(game.result.termination, Termination::Forfeit),
+        )
+        .unwrap();
+        assert_eq!((result.wins, result.draws, result.losses), (4, 0, 0));
+        assert_eq!(result.elo(), None);
+
+        let result = MatchResult {
+            wins: 60,
+            draws: 20,
+            losses: 20,
+            sprt: None,
+        };
+        let elo = result.elo().unwrap();
+        assert!((elo.diff - 147.2).abs() < 0.1, "{elo:?}");
+        assert!(elo.margin > 50.0 && elo.margin < 100.0, "{elo:?}");
+        let sprt = Sprt::new(0.0, 50.0);
+        assert!(result.llr(&sprt) > sprt.upper_bound());
+        assert!(result.llr(&Sprt::new(300.0, 350.0)) < sprt.lower_bound());
+    }
+}
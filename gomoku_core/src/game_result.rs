@@ -0,0 +1,175 @@
+//! How games end: with five in a row, a full board, a resignation, a loss on
+//! time or a forfeit, by agreement, or adjudicated as a draw once neither
+//! player can win anymore.
+
+use std::fmt::Display;
+
+use crate::gomoku::{Direction, State, Stone};
+
+/// What ended a game.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Termination {
+    /// Five in a row.
+    Line,
+    /// The board is full.
+    FullBoard,
+    /// The loser resigned.
+    Resignation,
+    /// The loser took too long to move.
+    Timeout,
+    /// The loser didn't return a legal move.
+    Forfeit,
+    /// Both players agreed to a draw.
+    Agreement,
+    /// The game was stopped as a draw, see `GameResult::adjudicate`.
+    Adjudication,
+}
+
+/// The result of a finished game.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GameResult {
+    /// None for a draw.
+    pub winner: Option<Stone>,
+    pub termination: Termination,
+}
+
+impl GameResult {
+    pub fn win(winner: Stone, termination: Termination) -> Self {
+        Self {
+            winner: Some(winner),
+            termination,
+        }
+    }
+
+    pub fn draw(termination: Termination) -> Self {
+        Self {
+            winner: None,
+            termination,
+        }
+    }
+
+    /// The result on the board: five in a row, or a full board.
+    pub fn of(state: &State) -> Option<Self> {
+        if let Some(line) = state.winning_line() {
+            return Some(Self::win(line.stone, Termination::Line));
+        }
+        (state.pieces.len() == 225).then(|| Self::draw(Termination::FullBoard))
+    }
+
+    /// Like `of`, but also a draw once neither player can make five anymore,
+    /// or once `draw_after` moves were played, when the board is nearly full
+    /// and games rarely end otherwise.
+    pub fn adjudicate(state: &State, draw_after: usize) -> Option<Self> {
+        Self::of(state).or_else(|| {
+            (state.pieces.len() >= draw_after || is_dead_draw(&state.board()))
+                .then(|| Self::draw(Termination::Adjudication))
+        })
+    }
+
+    /// The result in SGF notation, e.g. `B+`, `W+R` when black resigned, or
+    /// `0` for a draw.
+    pub fn to_sgf(&self) -> String {
+        let Some(winner) = self.winner else {
+            return "0".to_string();
+        };
+        let color = match winner {
+            Stone::Black => "B",
+            Stone::White => "W",
+        };
+        let reason = match self.termination {
+            Termination::Resignation => "R",
+            Termination::Timeout => "T",
+            Termination::Forfeit => "F",
+            _ => "",
+        };
+        format!("{color}+{reason}")
+    }
+}
+
+impl Display for GameResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let how = match self.termination {
+            Termination::Line => "with five in a row",
+            Termination::FullBoard => "on a full board",
+            Termination::Resignation => "by resignation",
+            Termination::Timeout => "on time",
+            Termination::Forfeit => "by forfeit",
+            Termination::Agreement => "by agreement",
+            Termination::Adjudication => "by adjudication",
+        };
+        match self.winner {
+            Some(winner) => write!(f, "{winner:?} wins {how}"),
+            None => write!(f, "Draw {how}"),
+        }
+    }
+}
+
+// Whether no five points in a row are free of the stones of one of the
+// players, so neither can ever make five.
+fn is_dead_draw(board: &[[Option<Stone>; 15]; 15]) -> bool {
+    let open = |stone: Stone| {
+        (0..225).any(|i| {
+            let (row, col) = (i / 15, i % 15);
+            Direction::ALL.into_iter().any(|direction| {
+                let (dr, dc) = direction.step();
+                (0..5).all(|i| {
+                    let (r, c) = (row as isize + i * dr, col as isize + i * dc);
+                    (0..15).contains(&r)
+                        && (0..15).contains(&c)
+                        && board[r as usize][c as usize] != Some(stone)
+                })
+            })
+        })
+    };
+    !open(Stone::Black) && !open(Stone::White)
+}
+
+#[test]
+fn test_game_result() {
+    use crate::gomoku::Move;
+
+    let won = State::new(
+        "a0 a1 b0 b1 c0 c1 d0 d1 e0"
+            .split(' ')
+            .map(|m| m.parse().unwrap())
+            .collect(),
+    );
+    let result = GameResult::of(&won).unwrap();
+    assert_eq!(result, GameResult::win(Stone::Black, Termination::Line));
+    assert_eq!(result.to_sgf(), "B+");
+    assert_eq!(result.to_string(), "Black wins with five in a row");
+    assert_eq!(GameResult::of(&State::default()), None);
+    assert_eq!(
+        GameResult::win(Stone::White, Termination::Resignation).to_sgf(),
+        "W+R"
+    );
+
+    let state = State::new(vec![Move::new(7, 7)]);
+    assert_eq!(GameResult::adjudicate(&state, 225), None);
+    assert_eq!(
+        GameResult::adjudicate(&state, 1),
+        Some(GameResult::draw(Termination::Adjudication))
+    );
+
+    // Rows of alternating pairs of stones, offset on every row, leave no five
+    // points in a row free of either color.
+    let diagram = (0..15)
+        .map(|row| {
+            (0..15)
+                .map(|col| match (row, col) {
+                    (14, 0) => '_',
+                    _ if (col / 2 + row) % 2 == 0 => 'X',
+                    _ => 'O',
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let dead = State::from_diagram(&diagram).unwrap();
+    assert!(is_dead_draw(&dead.board()));
+    assert_eq!(
+        GameResult::adjudicate(&dead, 225),
+        Some(GameResult::draw(Termination::Adjudication))
+    );
+    assert!(!is_dead_draw(&State::default().board()));
+}
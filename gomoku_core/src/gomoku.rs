@@ -1,30 +1,147 @@
 use super::interface::{Game, Winner};
 use std::fmt::{Debug, Display};
+use std::str::FromStr;
 pub struct Gomoku;
 
-#[derive(Default, Clone)]
+/// Which rows of stones win the game.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Rule {
+    /// Five or more stones in a row win.
+    #[default]
+    Freestyle,
+    /// Exactly five stones in a row win, longer rows don't.
+    Standard,
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "freestyle" => Ok(Rule::Freestyle),
+            "standard" => Ok(Rule::Standard),
+            _ => Err(format!(
+                "unknown rule \"{s}\", expected freestyle or standard"
+            )),
+        }
+    }
+}
+
+#[derive(Default, Clone, Debug)]
 pub struct State {
     pub pieces: Vec<Move>,
+    pub rule: Rule,
+}
+
+// Random keys for each stone color on each point, for Zobrist hashing.
+static ZOBRIST_KEYS: [[u64; 225]; 2] = zobrist_keys();
+
+const fn zobrist_keys() -> [[u64; 225]; 2] {
+    // splitmix64
+    let mut seed: u64 = 0x0123_4567_89AB_CDEF;
+    let mut keys = [[0; 225]; 2];
+    let mut i = 0;
+    while i < 450 {
+        seed = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        keys[i / 225][i % 225] = z ^ (z >> 31);
+        i += 1;
+    }
+    keys
 }
 
 impl State {
     pub fn new(moves: Vec<Move>) -> Self {
-        Self { pieces: moves }
+        Self {
+            pieces: moves,
+            rule: Rule::default(),
+        }
+    }
+
+    pub fn with_rule(mut self, rule: Rule) -> Self {
+        self.rule = rule;
+        self
+    }
+
+    /// The position transformed by `symmetry`, with moves in the same order.
+    pub fn transform(&self, symmetry: Symmetry) -> Self {
+        Self::new(self.pieces.iter().map(|m| m.transform(symmetry)).collect()).with_rule(self.rule)
+    }
+
+    // Stones of both colors, sorted, to compare positions regardless of the
+    // order the moves were played in.
+    fn stones(&self) -> [Vec<u8>; 2] {
+        let mut stones = [Vec::new(), Vec::new()];
+        for (i, m) in self.pieces.iter().enumerate() {
+            stones[i % 2].push(m.0);
+        }
+        stones[0].sort_unstable();
+        stones[1].sort_unstable();
+        stones
+    }
+
+    /// Whether both states have the same stones on the board.
+    pub fn same_position(&self, other: &State) -> bool {
+        self.pieces.len() == other.pieces.len() && self.stones() == other.stones()
+    }
+
+    /// The symmetries leaving this position unchanged, always including the
+    /// identity.
+    pub fn symmetries(&self) -> Vec<Symmetry> {
+        let stones = self.stones();
+        Symmetry::ALL
+            .into_iter()
+            .filter(|symmetry| {
+                *symmetry == Symmetry::Identity || self.transform(*symmetry).stones() == stones
+            })
+            .collect()
+    }
+
+    /// The canonical representative of this position among its symmetric
+    /// variants, and the symmetry transforming this state into it.
+    /// All symmetric positions have the same canonical form.
+    pub fn canonical(&self) -> (State, Symmetry) {
+        let (_, symmetry) = self.canonical_hash();
+        (self.transform(symmetry), symmetry)
+    }
+
+    /// Hash of the stones on the board, independent of the order they were
+    /// played in.
+    pub fn position_hash(&self) -> u64 {
+        self.transformed_hash(Symmetry::Identity)
+    }
+
+    /// Hash of the position transformed by `symmetry`.
+    pub fn transformed_hash(&self, symmetry: Symmetry) -> u64 {
+        self.pieces.iter().enumerate().fold(0, |hash, (i, m)| {
+            hash ^ ZOBRIST_KEYS[i % 2][m.transform(symmetry).0 as usize]
+        })
+    }
+
+    /// The smallest hash among all the symmetric positions, together with the
+    /// symmetry that produces it. Symmetric positions share the same
+    /// canonical hash.
+    pub fn canonical_hash(&self) -> (u64, Symmetry) {
+        Symmetry::ALL
+            .into_iter()
+            .map(|symmetry| (self.transformed_hash(symmetry), symmetry))
+            .min_by_key(|(hash, _)| *hash)
+            .unwrap()
     }
 
     pub fn player_to_move(&self) -> Stone {
-        if self.pieces.len() % 2 == 0 {
-            Stone::Black
-        } else {
-            Stone::White
+        match self.pieces.len() % 2 {
+            0 => Stone::Black,
+            _ => Stone::White,
         }
     }
 
     pub fn player_just_moved(&self) -> Stone {
-        if self.pieces.len() % 2 == 0 {
-            Stone::White
-        } else {
-            Stone::Black
+        match self.pieces.len() % 2 {
+            0 => Stone::White,
+            _ => Stone::Black,
         }
     }
 
@@ -34,6 +151,11 @@ impl State {
 
     #[inline]
     pub fn get_winner(&self) -> Option<Stone> {
+        self.winning_line().map(|line| line.stone)
+    }
+
+    /// The row of stones through the last move that won the game, if any.
+    pub fn winning_line(&self) -> Option<WinningLine> {
         let (row, col) = self.pieces.last()?.get_coord();
 
         let same_pieces = self
@@ -49,80 +171,142 @@ impl State {
             })
             .collect::<Vec<Move>>();
 
-        let mut board = [[None; 15]; 15];
+        let mut board = [[false; 15]; 15];
         for m in same_pieces.iter() {
             let (row, col) = m.get_coord();
-            board[row][col] = Some(Stone::Black);
+            board[row][col] = true;
         }
 
-        let mut count;
-        let mut max_count = 0;
-
-        count = 0;
-        // horizontal
-        for i in 0..15 {
-            if board[row][i].is_some() {
-                count += 1;
-            } else {
-                max_count = max_count.max(count);
-                count = 0;
-            }
-        }
+        // Stones in a row through the last move, in each direction.
+        let run = |dr: isize, dc: isize| {
+            (1..15)
+                .take_while(|i| {
+                    let (r, c) = (row as isize + i * dr, col as isize + i * dc);
+                    (0..15).contains(&r) && (0..15).contains(&c) && board[r as usize][c as usize]
+                })
+                .count()
+        };
+        Direction::ALL.into_iter().find_map(|direction| {
+            let (dr, dc) = direction.step();
+            let back = run(-dr, -dc);
+            let count = 1 + back + run(dr, dc);
+            let won = match self.rule {
+                Rule::Freestyle => count >= 5,
+                Rule::Standard => count == 5,
+            };
+            won.then(|| {
+                let (row, col) = (
+                    row as isize - back as isize * dr,
+                    col as isize - back as isize * dc,
+                );
+                let moves = (0..count as isize)
+                    .map(|i| Move::new((row + i * dr) as usize, (col + i * dc) as usize))
+                    .collect();
+                WinningLine {
+                    stone: self.player_just_moved(),
+                    moves,
+                    direction,
+                }
+            })
+        })
+    }
+}
 
-        count = 0;
-        // vertical
-        #[allow(clippy::needless_range_loop)]
-        for i in 0..15 {
-            if board[i][col].is_some() {
-                count += 1;
-            } else {
-                max_count = max_count.max(count);
-                count = 0;
-            }
-        }
+/// The directions of a row of stones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Horizontal,
+    Vertical,
+    // From the top left to the bottom right.
+    Diagonal,
+    // From the top right to the bottom left.
+    AntiDiagonal,
+}
 
-        count = 0;
-        // diagonal \
-        for (i, j) in (0..row)
-            .rev()
-            .zip((0..col).rev())
-            .rev()
-            .chain((row..15).zip(col..15))
-        {
-            if board[i][j].is_some() {
-                count += 1;
-            } else {
-                max_count = max_count.max(count);
-                count = 0;
-            }
-        }
+impl Direction {
+    pub const ALL: [Direction; 4] = [
+        Direction::Horizontal,
+        Direction::Vertical,
+        Direction::Diagonal,
+        Direction::AntiDiagonal,
+    ];
 
-        count = 0;
-        // diagonal /
-        for (i, j) in (0..row)
-            .rev()
-            .zip(col + 1..15)
-            .rev()
-            .chain((row..15).zip((0..=col).rev()))
-        {
-            if board[i][j].is_some() {
-                count += 1;
-            } else {
-                max_count = max_count.max(count);
-                count = 0;
-            }
+    /// The (row, column) offset from a stone of the row to the next one.
+    pub fn step(self) -> (isize, isize) {
+        match self {
+            Direction::Horizontal => (0, 1),
+            Direction::Vertical => (1, 0),
+            Direction::Diagonal => (1, 1),
+            Direction::AntiDiagonal => (1, -1),
         }
+    }
+}
 
-        if max_count >= 5 {
-            Some(self.player_just_moved())
+/// A row of stones winning the game.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WinningLine {
+    pub stone: Stone,
+    /// The stones of the row, in `direction` from one end to the other.
+    pub moves: Vec<Move>,
+    pub direction: Direction,
+}
+
+impl WinningLine {
+    /// Whether the row is longer than five, which only wins in freestyle.
+    pub fn is_overline(&self) -> bool {
+        self.moves.len() > 5
+    }
+}
+
+impl Display for WinningLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = if self.is_overline() {
+            "overline"
         } else {
-            None
-        }
+            "five"
+        };
+        let first = self.moves.first().unwrap();
+        let last = self.moves.last().unwrap();
+        write!(f, "{:?} {kind} from {first} to {last}", self.stone)
+    }
+}
+
+#[test]
+fn test_rule() {
+    // Black plays six in a row at the edge of the board, the last stone in the
+    // middle.
+    let black = [10, 11, 13, 14, 9, 12].map(|col| Move::new(0, col));
+    let white = [2, 3, 4, 5, 6].map(|col| Move::new(5, col));
+    let mut pieces = Vec::new();
+    for (i, m) in black.iter().enumerate() {
+        pieces.push(*m);
+        pieces.extend(white.get(i));
     }
+    let five = State::new(pieces[..9].to_vec());
+    assert_eq!(five.get_winner(), None);
+    let five = State::new([&pieces[..8], &pieces[10..]].concat());
+    assert_eq!(five.get_winner(), Some(Stone::Black));
+    let overline = State::new(pieces.clone());
+    assert_eq!(overline.get_winner(), Some(Stone::Black));
+    let line = overline.winning_line().unwrap();
+    assert_eq!(
+        line.moves,
+        (9..15).map(|col| Move::new(0, col)).collect::<Vec<_>>()
+    );
+    assert_eq!(line.direction, Direction::Horizontal);
+    assert!(line.is_overline());
+    assert_eq!(line.to_string(), "Black overline from j0 to o0");
+    assert!(overline
+        .to_string()
+        .contains(" 0 _ _ _ _ _ _ _ _ _ x x x x x x"));
+    assert_eq!(overline.with_rule(Rule::Standard).get_winner(), None);
+    assert_eq!("Standard".parse(), Ok(Rule::Standard));
 }
 
+// Stones of the winning line are lowercase.
 impl Display for State {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let line = self.winning_line().map_or(Vec::new(), |line| line.moves);
         let mut board = [[None; 15]; 15];
         for (i, pieces) in self.pieces.iter().enumerate() {
             let (row, col) = pieces.get_coord();
@@ -131,16 +315,19 @@ impl Display for State {
             board[row][col] = Some(stone);
         }
         writeln!(f, "   a b c d e f g h i j k l m n o")?;
-        for (row, line) in board.iter().enumerate() {
+        for (row, points) in board.iter().enumerate() {
             write!(f, "{:2} ", row)?;
-            for stone in line {
+            for (col, stone) in points.iter().enumerate() {
                 if let Some(stone) = stone {
+                    let won = line.contains(&Move::new(row, col));
                     write!(
                         f,
                         "{}",
-                        match stone {
-                            Stone::Black => "X ",
-                            Stone::White => "O ",
+                        match (stone, won) {
+                            (Stone::Black, false) => "X ",
+                            (Stone::White, false) => "O ",
+                            (Stone::Black, true) => "x ",
+                            (Stone::White, true) => "o ",
                         }
                     )?;
                 } else {
@@ -159,6 +346,16 @@ pub enum Stone {
     White,
 }
 
+impl Stone {
+    /// The stone of the other player.
+    pub fn opponent(self) -> Stone {
+        match self {
+            Stone::Black => Stone::White,
+            Stone::White => Stone::Black,
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub struct Move(u8);
 //   0   1   2  ...  12  13  14
@@ -178,8 +375,124 @@ impl Move {
         // row, col
         (self.0 as usize / 15, self.0 as usize % 15)
     }
+    /// The index of this move on the board, see the layout above.
+    pub fn index(&self) -> u8 {
+        self.0
+    }
+    /// The move at the same point of the board transformed by `symmetry`.
+    pub fn transform(&self, symmetry: Symmetry) -> Self {
+        let (row, col) = symmetry.apply(self.get_coord());
+        Move::new(row, col)
+    }
+}
+
+/// The 8 symmetries of the board, rotating or reflecting it around its center.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Symmetry {
+    Identity,
+    // Clockwise.
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    // Reflect columns.
+    FlipHorizontal,
+    // Reflect rows.
+    FlipVertical,
+    // Swap rows and columns.
+    FlipDiagonal,
+    FlipAntiDiagonal,
+}
+
+impl Symmetry {
+    pub const ALL: [Symmetry; 8] = [
+        Symmetry::Identity,
+        Symmetry::Rotate90,
+        Symmetry::Rotate180,
+        Symmetry::Rotate270,
+        Symmetry::FlipHorizontal,
+        Symmetry::FlipVertical,
+        Symmetry::FlipDiagonal,
+        Symmetry::FlipAntiDiagonal,
+    ];
+
+    /// The symmetry undoing this one.
+    pub fn inverse(self) -> Self {
+        match self {
+            Symmetry::Rotate90 => Symmetry::Rotate270,
+            Symmetry::Rotate270 => Symmetry::Rotate90,
+            other => other,
+        }
+    }
+
+    /// The symmetry applying `self` first, then `other`.
+    pub fn then(self, other: Symmetry) -> Self {
+        // A point off the center lines and diagonals tells all symmetries apart.
+        let probe = (2, 5);
+        let target = other.apply(self.apply(probe));
+        Symmetry::ALL
+            .into_iter()
+            .find(|symmetry| symmetry.apply(probe) == target)
+            .unwrap()
+    }
+
+    /// Transform a (row, col) coordinate.
+    pub fn apply(self, (row, col): (usize, usize)) -> (usize, usize) {
+        use Symmetry::*;
+        match self {
+            Identity => (row, col),
+            Rotate90 => (col, 14 - row),
+            Rotate180 => (14 - row, 14 - col),
+            Rotate270 => (14 - col, row),
+            FlipHorizontal => (row, 14 - col),
+            FlipVertical => (14 - row, col),
+            FlipDiagonal => (col, row),
+            FlipAntiDiagonal => (14 - col, 14 - row),
+        }
+    }
+}
+
+#[test]
+fn test_canonical() {
+    let state = State::new(vec![Move::new(7, 7), Move::new(6, 8), Move::new(3, 4)]);
+    let (canonical, _) = state.canonical();
+    for symmetry in Symmetry::ALL {
+        let transformed = state.transform(symmetry);
+        assert_eq!(transformed.canonical_hash().0, state.canonical_hash().0);
+        assert!(transformed.canonical().0.same_position(&canonical));
+        for other in Symmetry::ALL {
+            let m = Move::new(2, 5);
+            assert_eq!(
+                m.transform(symmetry.then(other)),
+                m.transform(symmetry).transform(other)
+            );
+        }
+    }
+    assert_eq!(State::default().symmetries().len(), 8);
+    assert_eq!(
+        State::new(vec![Move::new(7, 7), Move::new(6, 6)])
+            .symmetries()
+            .len(),
+        2
+    );
+    assert_eq!(state.symmetries(), vec![Symmetry::Identity]);
+}
+
+#[test]
+fn test_symmetry() {
+    let m = Move::new(2, 5);
+    for symmetry in Symmetry::ALL {
+        assert_eq!(m.transform(symmetry).transform(symmetry.inverse()), m);
+    }
+    assert_eq!(m.transform(Symmetry::Rotate90), Move::new(5, 12));
+    assert_eq!(
+        Move::new(7, 7).transform(Symmetry::FlipAntiDiagonal),
+        Move::new(7, 7)
+    );
 }
 
+// Comparisons order moves by their index on the board, while `Ord` orders
+// them by their distance to the center.
+#[allow(clippy::non_canonical_partial_ord_impl)]
 impl PartialOrd for Move {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         Some(self.0.cmp(&other.0))
@@ -216,9 +529,46 @@ impl Debug for Move {
     }
 }
 
+// The column letter followed by the row number, e.g. `h7` for the center.
 impl Display for Move {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "M({},{})", ((self.0 % 15) + b'a') as char, self.0 / 15)
+        write!(f, "{}{}", ((self.0 % 15) + b'a') as char, self.0 / 15)
+    }
+}
+
+/// The error returned when parsing a `Move` fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseMoveError(String);
+
+impl Display for ParseMoveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid move \"{}\", expected e.g. \"h7\"", self.0)
+    }
+}
+
+impl std::error::Error for ParseMoveError {}
+
+// Parses the format of Display, ignoring case and whitespace, e.g. `H 7`.
+impl FromStr for Move {
+    type Err = ParseMoveError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseMoveError(s.to_string());
+        let s = s.trim().to_ascii_lowercase();
+        let mut chars = s.chars();
+        let col = match chars.next() {
+            Some(c @ 'a'..='o') => c as usize - 'a' as usize,
+            _ => return Err(error()),
+        };
+        let row = chars
+            .as_str()
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| error())?;
+        if row >= 15 {
+            return Err(error());
+        }
+        Ok(Move::new(row, col))
     }
 }
 
@@ -257,4 +607,8 @@ impl Game for Gomoku {
             _ => None,
         }
     }
+
+    fn zobrist_hash(state: &Self::S) -> u64 {
+        state.position_hash()
+    }
 }
@@ -112,4 +112,10 @@ pub trait Strategy<G: Game> {
     fn principal_variation(&self) -> Vec<G::M> {
         Vec::new()
     }
+
+    /// Whether the strategy gives up the game instead of playing the move
+    /// returned by the last choose_move call.
+    fn resigns(&self) -> bool {
+        false
+    }
 }
@@ -0,0 +1,30 @@
+//! Helpers shared by the readers of the file formats.
+
+use std::io::{self, Read};
+
+use crate::gomoku::Rule;
+
+pub(crate) fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+pub(crate) fn read_array<const N: usize, R: Read>(reader: &mut R) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+pub(crate) fn rule_byte(rule: Rule) -> u8 {
+    match rule {
+        Rule::Freestyle => 0,
+        Rule::Standard => 1,
+    }
+}
+
+pub(crate) fn read_rule<R: Read>(reader: &mut R) -> io::Result<Rule> {
+    match read_array::<1, _>(reader)? {
+        [0] => Ok(Rule::Freestyle),
+        [1] => Ok(Rule::Standard),
+        _ => Err(invalid_data("unknown rule")),
+    }
+}
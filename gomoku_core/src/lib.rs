@@ -1,3 +1,10 @@
+pub mod book;
+pub mod engine_match;
+pub mod game_result;
 pub mod gomoku;
 pub mod interface;
+pub(crate) mod io_util;
 pub mod mcts;
+pub mod position;
+pub mod puzzle;
+pub mod record;
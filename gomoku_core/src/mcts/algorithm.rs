@@ -1,14 +1,102 @@
-use std::sync::{atomic::Ordering::*, Arc, RwLock};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering::*};
+use std::sync::{mpsc::Receiver, Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 
+use arc_swap::ArcSwap;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::analysis::{self, Analysis, AnalysisOptions, MoveInfo, PonderResult, Proof};
+use super::arena::CHUNK_SIZE;
+use super::move_selection::MoveSelection;
+use super::persistence;
+use super::pool::{SearchPool, Session};
 use super::rollout_policy::RolloutPolicy;
 use super::{LOSS, WIN};
 
-use crate::gomoku::{Gomoku, Move, State};
-use crate::interface::{Game, Winner};
+use crate::book::OpeningBook;
+use crate::gomoku::{Gomoku, Move, State, Stone};
+use crate::interface::{Game, Strategy, Winner};
+
+use super::node::Node;
+use super::tree::Tree;
+
+/// The formula used to select which child to search next.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UctFormula {
+    /// The classic UCB1 formula.
+    #[default]
+    Ucb1,
+    /// UCB1-Tuned, which scales the exploration term by an upper bound of the
+    /// variance of each child's results.
+    Ucb1Tuned,
+}
+
+impl FromStr for UctFormula {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "ucb1" => Ok(UctFormula::Ucb1),
+            "tuned" => Ok(UctFormula::Ucb1Tuned),
+            _ => Err(format!("unknown formula \"{s}\", expected ucb1 or tuned")),
+        }
+    }
+}
+
+/// Progressive widening: a node only gets children for its most promising
+/// moves at first, by a quick heuristic, and more of them as it gets visited.
+/// A node visited `n` times searches about
+/// `max(initial_children, constant * n^exponent)` children.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProgressiveWidening {
+    pub initial_children: usize,
+    pub constant: f32,
+    pub exponent: f32,
+}
+
+impl Default for ProgressiveWidening {
+    fn default() -> Self {
+        Self {
+            initial_children: 4,
+            constant: 2.0,
+            exponent: 0.5,
+        }
+    }
+}
+
+impl ProgressiveWidening {
+    // How many children a node visited `visits` times should search.
+    fn num_children(&self, visits: u32) -> usize {
+        let widened = (self.constant * (visits as f32).powf(self.exponent)).ceil() as usize;
+        widened.max(self.initial_children)
+    }
+}
+
+/// When the engine gives up a game: as soon as the search proves it lost, or
+/// once the move it chose had a win rate below `threshold` for `moves` moves
+/// in a row. Moves visited less than `min_visits` times don't count, as their
+/// win rate is too noisy.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Resignation {
+    pub threshold: f64,
+    pub moves: u32,
+    pub min_visits: u32,
+}
 
-use super::node::{new_expansion, Node};
+impl Default for Resignation {
+    fn default() -> Self {
+        Self {
+            threshold: 0.05,
+            moves: 3,
+            min_visits: 1000,
+        }
+    }
+}
 
 /// Options for MonteCarloTreeSearch.
 #[derive(Clone)]
@@ -18,6 +106,25 @@ pub struct MCTSOptions {
     rollouts_before_expanding: u32,
     // None means use num_cpus.
     num_threads: Option<usize>,
+    multi_pv: usize,
+    move_selection: MoveSelection,
+    pub(super) exploration: f32,
+    pub(super) uct_formula: UctFormula,
+    // None means unvisited children are always searched first.
+    pub(super) first_play_urgency: Option<f32>,
+    opening_book: Option<Arc<OpeningBook>>,
+    pub(super) symmetry_ply: usize,
+    // None means unbounded.
+    max_nodes: Option<usize>,
+    // None means nodes are expanded with all their children at once.
+    progressive_widening: Option<ProgressiveWidening>,
+    // None means seeded from the OS.
+    seed: Option<u64>,
+    // None means the background threads search for the timeout instead.
+    playouts: Option<u32>,
+    // None means the engine never resigns.
+    resignation: Option<Resignation>,
+    ponder: bool,
 }
 
 impl Default for MCTSOptions {
@@ -27,6 +134,19 @@ impl Default for MCTSOptions {
             max_rollout_depth: 100,
             rollouts_before_expanding: 5,
             num_threads: None,
+            multi_pv: 1,
+            move_selection: MoveSelection::default(),
+            exploration: 1.,
+            uct_formula: UctFormula::default(),
+            first_play_urgency: None,
+            opening_book: Some(Arc::new(OpeningBook::center_opening())),
+            symmetry_ply: 0,
+            max_nodes: None,
+            progressive_widening: None,
+            seed: None,
+            playouts: None,
+            resignation: None,
+            ponder: false,
         }
     }
 }
@@ -58,187 +178,947 @@ impl MCTSOptions {
         self.num_threads = Some(threads);
         self
     }
+
+    /// Keep the search balanced between the `k` best moves of the current
+    /// state, instead of focusing on the single best one. Useful together with
+    /// `MonteCarloTreeSearchContinue::multi_pv`. Defaults to 1.
+    pub fn with_multi_pv(mut self, k: usize) -> Self {
+        self.multi_pv = k.max(1);
+        self
+    }
+
+    /// How to choose the final move. Defaults to the most visited move.
+    pub fn with_move_selection(mut self, selection: MoveSelection) -> Self {
+        self.move_selection = selection;
+        self
+    }
+
+    /// The exploration constant of the selection formula. Higher values
+    /// search more moves, lower values search the best moves deeper.
+    /// Defaults to 1.
+    pub fn with_exploration(mut self, exploration: f32) -> Self {
+        self.exploration = exploration;
+        self
+    }
+
+    /// The formula used to select which child to search next.
+    /// Defaults to UCB1.
+    pub fn with_uct_formula(mut self, formula: UctFormula) -> Self {
+        self.uct_formula = formula;
+        self
+    }
+
+    /// Give unvisited children this score instead of searching each of them
+    /// once before revisiting any sibling. Values around the expected win
+    /// rate (0.0 to 1.0) focus the search on the most promising moves.
+    pub fn with_first_play_urgency(mut self, urgency: f32) -> Self {
+        self.first_play_urgency = Some(urgency);
+        self
+    }
+
+    /// Play from this book instead of searching whenever it knows the
+    /// current position. Defaults to a book that only opens in the center.
+    pub fn with_opening_book(mut self, book: Arc<OpeningBook>) -> Self {
+        self.opening_book = Some(book);
+        self
+    }
+
+    /// Always search, even for the first move.
+    pub fn without_opening_book(mut self) -> Self {
+        self.opening_book = None;
+        self
+    }
+
+    /// During the first `ply` moves of the game, only search one of the moves
+    /// leading to symmetric positions, e.g. one of the 8 equivalent second
+    /// moves next to the center stone. Defaults to 0.
+    pub fn with_symmetry_pruning(mut self, ply: usize) -> Self {
+        self.symmetry_ply = ply;
+        self
+    }
+
+    /// Stop growing the tree once it holds this many nodes. The search goes
+    /// on, but leaves are rolled out instead of being expanded, until the
+    /// position changes: the tree is then replaced by the most visited part
+    /// of the subtree of the new position, up to a quarter of the budget, so
+    /// the statistics of every other position are lost, e.g. for `take_back`.
+    /// Defaults to unbounded.
+    pub fn with_max_nodes(mut self, max_nodes: usize) -> Self {
+        self.max_nodes = Some(max_nodes);
+        self
+    }
+
+    /// Like `with_max_nodes`, but for the memory used by the nodes, in bytes.
+    /// Nodes are allocated `CHUNK_SIZE` at a time, so the budget is rounded
+    /// down to whole chunks. One of them is kept for the nodes expanded to
+    /// reach the positions played, which are expanded whatever the budget,
+    /// and a fifth of the rest for the pruned tree replacing a full one.
+    /// Other allocations of the search are small in comparison, but aren't
+    /// accounted for, so keep some headroom.
+    pub fn with_max_memory(self, bytes: usize) -> Self {
+        let chunks = bytes / (CHUNK_SIZE * std::mem::size_of::<Node>());
+        self.with_max_nodes(chunks.saturating_sub(1) * 4 / 5 * CHUNK_SIZE)
+    }
+
+    /// Add the children of a node progressively, most promising moves first,
+    /// instead of all of them when it is expanded. Uses much less memory, and
+    /// focuses the search on the moves near the stones.
+    /// Defaults to off.
+    pub fn with_progressive_widening(mut self, widening: ProgressiveWidening) -> Self {
+        self.progressive_widening = Some(widening);
+        self
+    }
+
+    /// Seed the random number generators of the search. Each thread gets its
+    /// own generator derived from the seed. Only reproducible together with
+    /// `with_playouts`, as threads are scheduled nondeterministically.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Search deterministically: `choose_move` runs this many playouts on the
+    /// calling thread instead of waiting for the timeout, and
+    /// `start_simulating` doesn't start any background threads.
+    /// `MoveSelection::RobustMax` runs at most as many extra playouts.
+    pub fn with_playouts(mut self, playouts: u32) -> Self {
+        self.playouts = Some(playouts);
+        self
+    }
+
+    /// After choosing a move, search the position after the opponent's most
+    /// likely reply until the opponent moves, instead of all their replies.
+    /// See `MonteCarloTreeSearchContinue::ponder_result` for how it went.
+    pub fn ponder(mut self) -> Self {
+        self.ponder = true;
+        self
+    }
+
+    /// Let the engine resign lost games, see `MonteCarloTreeSearchContinue::resigns`.
+    /// Defaults to off.
+    pub fn with_resignation(mut self, resignation: Resignation) -> Self {
+        self.resignation = Some(resignation);
+        self
+    }
+
+    pub(super) fn is_seeded(&self) -> bool {
+        self.seed.is_some()
+    }
+
+    // The random number generator of the `index`th thread.
+    pub(super) fn rng(&self, index: u64) -> StdRng {
+        match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed.wrapping_add(index)),
+            None => StdRng::from_entropy(),
+        }
+    }
 }
 
 pub struct MonteCarloTreeSearchContinue {
-    tree: Arc<Node>,
-    cur_state: Arc<RwLock<State>>,
+    // Replaced by a pruned tree once it is full.
+    tree: Arc<ArcSwap<Tree>>,
     options: MCTSOptions,
     time_out: Duration,
     pre_rollouts_count: u32,
     pre_choose_move_time: Instant,
+    // For the searches and choices made on the calling thread.
+    rng: StdRng,
+    // Tells the background threads to exit.
+    stop: Arc<AtomicBool>,
+    // Moves in a row chosen with a win rate below the resignation threshold.
+    losing_moves: u32,
+    ponder: Arc<Mutex<Ponder>>,
+    // Set when searching on the threads of a pool.
+    session: Option<Arc<Session>>,
+}
+
+#[derive(Default)]
+pub(super) struct Ponder {
+    // The position after the engine's move and the predicted reply, while
+    // pondering on it.
+    pending: Option<(State, Move)>,
+    // How the last ponder went.
+    last: Option<PonderResult>,
+}
+
+impl Ponder {
+    // The position pondered on, or else the search root of `tree`.
+    pub(super) fn cur_state(&self, tree: &Tree) -> State {
+        match &self.pending {
+            Some((position, _)) => position.clone(),
+            None => tree.search_root().state.clone(),
+        }
+    }
 }
 
 impl MonteCarloTreeSearchContinue {
     pub fn new(options: MCTSOptions) -> Self {
-        let cur_state = State::default();
-        let tree = Node::new(None);
-        tree.expansion.try_set(new_expansion(&cur_state));
+        let tree = Tree::new(options.max_nodes);
+        Self::with_tree(options, tree)
+    }
+
+    /// Warm start from a search tree saved with `save_tree`.
+    pub fn load_tree(options: MCTSOptions, path: impl AsRef<Path>) -> io::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        let tree = persistence::load_tree(reader, options.max_nodes)?;
+        Ok(Self::with_tree(options, tree))
+    }
+
+    /// Save the search tree, so it can be reused with `load_tree`.
+    /// Subtrees of nodes visited less than `min_visits` times are pruned.
+    pub fn save_tree(&self, path: impl AsRef<Path>, min_visits: u32) -> io::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        persistence::save_tree(&self.tree.load(), writer, min_visits, None)
+    }
+
+    /// Like `save_tree`, but only save the `max_nodes` most visited nodes,
+    /// to cap the size of the file, at 21 bytes per node.
+    pub fn save_tree_capped(
+        &self,
+        path: impl AsRef<Path>,
+        min_visits: u32,
+        max_nodes: usize,
+    ) -> io::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        persistence::save_tree(&self.tree.load(), writer, min_visits, Some(max_nodes))
+    }
+
+    /// Add the statistics of the first `max_ply` moves of the search tree
+    /// to `book`, skipping moves visited less than `min_visits` times.
+    pub fn add_to_book(&self, book: &mut OpeningBook, min_visits: u32, max_ply: usize) {
+        book.add_tree(&self.tree.load(), min_visits, max_ply);
+    }
+
+    fn with_tree(options: MCTSOptions, tree: Tree) -> Self {
+        tree.expand(
+            &tree.root,
+            &State::default(),
+            options.symmetry_ply > 0,
+            true,
+        );
+        let pre_rollouts_count = tree.root.visits.load(Relaxed);
 
         Self {
-            tree: Arc::new(tree),
-            cur_state: Arc::new(RwLock::new(cur_state)),
+            tree: Arc::new(ArcSwap::from_pointee(tree)),
+            rng: options.rng(0),
+            stop: Arc::new(AtomicBool::new(false)),
             options,
             time_out: Duration::from_secs(5),
-            pre_rollouts_count: 0,
+            pre_rollouts_count,
             pre_choose_move_time: Instant::now(),
+            losing_moves: 0,
+            ponder: Arc::default(),
+            session: None,
         }
     }
     pub fn choose_move(&mut self, state: &State) -> Option<Move> {
-        self.change_cur_state(state);
-        if self.cur_state.read().unwrap().pieces.is_empty() {
-            return Some(Move::new(7, 7));
+        self.set_cur_state(state, false);
+        if let Some(book) = &self.options.opening_book {
+            if let Some(m) = book.choose_move(state, &mut self.rng) {
+                return Some(m);
+            }
         }
 
-        thread::sleep(self.time_out);
+        let tree = self.tree.load_full();
+        match self.options.playouts {
+            Some(playouts) => {
+                for _ in 0..playouts {
+                    Self::search_once(&self.options, &tree, &mut self.rng);
+                }
+            }
+            None => thread::sleep(self.time_out),
+        }
 
-        let cur_node = self
-            .tree
-            .get_to_node(&self.cur_state.read().unwrap().pieces)
-            .expect("cur_node and cur_state not match!");
+        let root = tree.search_root();
+        let found = root.found(&tree).expect("cur_state is not a legal state!");
+        let cur_node = found.node;
+
+        if let MoveSelection::RobustMax { max_extra_time } = self.options.move_selection {
+            let deadline = Instant::now() + max_extra_time;
+            let mut extra_playouts = self.options.playouts;
+            while self
+                .options
+                .move_selection
+                .needs_more_search(cur_node.children(&tree.arena)?)
+            {
+                match &mut extra_playouts {
+                    Some(0) => break,
+                    Some(playouts) => {
+                        *playouts -= 1;
+                        Self::search_once(&self.options, &tree, &mut self.rng);
+                    }
+                    None if Instant::now() >= deadline => break,
+                    None => thread::sleep(Duration::from_millis(10)),
+                }
+            }
+        }
 
         if self.options.verbose {
-            let total_visits = self.tree.visits.load(Relaxed);
+            let total_visits = tree.root.visits.load(Relaxed);
             let duration = Instant::now().duration_since(self.pre_choose_move_time);
-            let rate = (total_visits - self.pre_rollouts_count) as f64 / duration.as_secs_f64();
+            let rate = total_visits.saturating_sub(self.pre_rollouts_count) as f64
+                / duration.as_secs_f64();
             eprintln!(
-                "Did {} total simulations with {:.1} rollouts/sec",
-                total_visits, rate
+                "Did {} total simulations with {:.1} rollouts/sec, {} nodes in the tree",
+                total_visits,
+                rate,
+                tree.num_nodes()
             );
-            // Sort moves by visit count, largest first.
-            let mut children = cur_node
-                .expansion
-                .get()?
-                .children
-                .iter()
-                .map(|node| (node.visits.load(Relaxed), node.score.load(Relaxed), node.m))
-                .collect::<Vec<_>>();
-            children.sort_by_key(|t| !t.0);
-
+            if let Some(ponder) = self.ponder_result() {
+                let hit = if ponder.hit { "hit" } else { "miss" };
+                eprintln!(
+                    "Ponder {hit} on {}, {} playouts carried over",
+                    ponder.predicted, ponder.playouts
+                );
+            }
             // Dump stats about the top 10 nodes.
-            for (visits, score, m) in children.into_iter().take(10) {
-                // Normalized so all wins is 100%, all draws is 50%, and all losses is 0%.
-                let win_rate = (score as f64 + visits as f64) / (visits as f64 * 2.0);
+            for info in analysis::analyze(&tree, &root.state, 10, 0)?.moves {
                 println!(
                     "{:>6} visits, {:.02}% wins: {}",
-                    visits,
-                    win_rate * 100.0,
-                    m.unwrap()
+                    info.visits,
+                    info.win_rate * 100.0,
+                    info.m
                 );
             }
         }
 
-        // get most visited node
-        let node = cur_node
-            .expansion
-            .get()?
-            .children
-            .iter()
-            .max_by_key(|n| n.visits.load(Relaxed))?;
-        println!("final visits: {}", node.visits.load(Relaxed));
-        node.m
+        let node = self
+            .options
+            .move_selection
+            .select(cur_node.children(&tree.arena)?, &mut self.rng)?;
+        if self.options.verbose {
+            println!("final visits: {}", node.visits.load(Relaxed));
+        }
+        if let Some(resignation) = self.options.resignation {
+            // The position is proven won by the opponent. The win rate of a
+            // move proven to win doesn't count its wins.
+            self.losing_moves = if cur_node.proven_value() == Some(1) {
+                resignation.moves.max(1)
+            } else if node.proven_value() != Some(1)
+                && node.visits.load(Relaxed) >= resignation.min_visits
+                && node.win_rate() < resignation.threshold
+            {
+                self.losing_moves + 1
+            } else {
+                0
+            };
+        }
+        let inverse = found.symmetry.inverse();
+        let m = node.m.map(|m| m.transform(inverse));
+        if self.options.ponder {
+            // The most visited reply, in the orientation of `state`.
+            let reply = node.most_visited_child(&tree.arena);
+            let reply = reply
+                .and_then(|reply| reply.m)
+                .map(|m| m.transform(inverse));
+            if let (Some(m), Some(reply)) = (m, reply) {
+                self.start_pondering(state, m, reply);
+            }
+        }
+        m
+    }
+
+    // Search the position after `m` and `reply` until the opponent moves.
+    fn start_pondering(&self, state: &State, m: Move, reply: Move) {
+        let mut position = state.clone();
+        Gomoku::apply(&mut position, &m);
+        let mut predicted = position.clone();
+        Gomoku::apply(&mut predicted, &reply);
+        self.set_search_root(&predicted);
+        self.ponder.lock().unwrap().pending = Some((position, reply));
+    }
+
+    /// How pondering on the last predicted reply went, once the opponent
+    /// replied. Only with `MCTSOptions::ponder`.
+    pub fn ponder_result(&self) -> Option<PonderResult> {
+        self.ponder.lock().unwrap().last
+    }
+
+    /// Whether the engine gives up the game instead of playing the move it
+    /// just chose. Never with the default options, see `with_resignation`.
+    pub fn resigns(&self) -> bool {
+        self.options
+            .resignation
+            .is_some_and(|resignation| self.losing_moves >= resignation.moves.max(1))
+    }
+
+    /// Whether the engine, playing `stone`, accepts a draw in the current
+    /// state: it does unless it expects to win.
+    pub fn accepts_draw(&self, stone: Stone) -> bool {
+        let Some(analysis) = self.analysis(1) else {
+            return true;
+        };
+        let Some(best) = analysis.moves.first() else {
+            return true;
+        };
+        // The expected score of the player to move.
+        let score = match best.proven {
+            Some(Proof::Win) => 1.0,
+            Some(Proof::Draw) => 0.5,
+            Some(Proof::Loss) => 0.0,
+            None => best.win_rate,
+        };
+        if analysis.state.player_to_move() == stone {
+            score <= 0.5
+        } else {
+            score >= 0.5
+        }
     }
 
+    /// Search `state` from now on. Never waits for the background threads,
+    /// which discard the simulations they started from the previous state.
+    /// While pondering, the position after the engine's move keeps the
+    /// search on the predicted reply, and any other ends the ponder.
     pub fn change_cur_state(&self, state: &State) {
-        let mut cur_state = self.cur_state.write().unwrap();
-        *cur_state = state.clone();
+        self.set_cur_state(state, true);
+    }
+
+    // Like change_cur_state, but a ponder on the predicted reply to `state`
+    // is only kept if `keep_pondering` is set.
+    fn set_cur_state(&self, state: &State, keep_pondering: bool) {
+        let mut ponder = self.ponder.lock().unwrap();
+        let Some((position, reply)) = ponder.pending.take() else {
+            self.set_search_root(state);
+            return;
+        };
+        if state.pieces == position.pieces && state.rule == position.rule {
+            if keep_pondering {
+                ponder.pending = Some((position, reply));
+            } else {
+                // The engine plays the other side too.
+                self.set_search_root(state);
+            }
+            return;
+        }
+        self.set_search_root(state);
+        // Any other state than a reply to the position, like a take back or a
+        // new game, abandons the ponder.
+        let replied = state.rule == position.rule
+            && state.pieces.len() == position.pieces.len() + 1
+            && state.pieces.starts_with(&position.pieces);
+        if !replied {
+            return;
+        }
+        let tree = self.tree.load();
+        let root = tree.search_root();
+        let playouts = root
+            .found(&tree)
+            .map_or(0, |found| found.node.visits.load(Relaxed));
+        ponder.last = Some(PonderResult {
+            predicted: reply,
+            hit: state.pieces.last() == Some(&reply),
+            playouts,
+        });
+    }
+
+    /// The state being searched, or while pondering, the position after the
+    /// engine's move.
+    pub fn cur_state(&self) -> State {
+        self.ponder.lock().unwrap().cur_state(&self.tree.load())
+    }
+
+    // Search `state` from now on. Once the tree is full, it is replaced by a
+    // tree pruned to `state`, to free the nodes of the earlier positions, and
+    // by a new tree when the rule changes.
+    fn set_search_root(&self, state: &State) {
+        let tree = self.tree.load();
+        if tree.rule() != state.rule {
+            let tree = Tree::new(self.options.max_nodes);
+            tree.set_search_root(state, self.options.symmetry_ply);
+            self.tree.store(Arc::new(tree));
+        } else if tree.is_full() && tree.search_root().state.pieces != state.pieces {
+            let pruned = tree.pruned(state, self.options.symmetry_ply);
+            self.tree.store(Arc::new(pruned));
+        } else {
+            tree.set_search_root(state, self.options.symmetry_ply);
+        }
+    }
+
+    /// Take back the last `moves` moves of the current state, and search the
+    /// earlier position from now on. As the tree is rooted at the empty board,
+    /// the statistics of that position and its subtree are all kept,
+    /// including the ones gathered while searching the later positions,
+    /// unless the tree was pruned in between, see `MCTSOptions::with_max_nodes`.
+    /// Returns the new current state.
+    pub fn take_back(&self, moves: usize) -> State {
+        let mut state = self.cur_state();
+        for _ in 0..moves {
+            let Some(m) = state.pieces.last().copied() else {
+                break;
+            };
+            Gomoku::undo(&mut state, &m);
+        }
+        self.change_cur_state(&state);
+        state
     }
 
     pub fn start_simulating(&mut self) {
         self.pre_choose_move_time = Instant::now();
-        let num_threads = self.options.num_threads.unwrap_or_else(num_cpus::get) as u32;
+        if self.options.playouts.is_some() {
+            return;
+        }
+        let num_threads = self.options.num_threads.unwrap_or_else(num_cpus::get) as u64;
 
-        for _ in 0..num_threads {
-            let state = Arc::clone(&self.cur_state);
+        for index in 1..=num_threads {
             let tree = Arc::clone(&self.tree);
             let options = self.options.clone();
-            thread::spawn(move || loop {
-                let mut state = { state.read().unwrap().clone() };
-                let node = tree
-                    .get_to_node(&state.pieces)
-                    .expect("cur_node and cur_state not match!");
-                let reward = Self::simulate_once(&options, node, &mut state, false);
-                tree.propagate_reward(reward, &state.pieces);
+            let mut rng = options.rng(index);
+            let stop = Arc::clone(&self.stop);
+            thread::spawn(move || {
+                while !stop.load(Relaxed) {
+                    Self::search_once(&options, &tree.load(), &mut rng);
+                }
             });
         }
     }
 
+    /// Like `start_simulating`, but search on the threads of `pool`, shared
+    /// with the other engines started in it, instead of starting threads of
+    /// its own. Engines with a higher `priority` get proportionally more
+    /// playouts. The engine leaves the pool when it is dropped.
+    pub fn start_simulating_in(&mut self, pool: &SearchPool, priority: u32) {
+        self.pre_choose_move_time = Instant::now();
+        if self.options.playouts.is_some() || self.session.is_some() {
+            return;
+        }
+        let session = Arc::new(Session::new(
+            Arc::clone(&self.tree),
+            self.options.clone(),
+            Arc::clone(&self.stop),
+        ));
+        session.set_priority(priority);
+        pool.add(Arc::clone(&session));
+        self.session = Some(session);
+    }
+
+    /// Change the priority of the engine in the pool it searches in, see
+    /// `start_simulating_in`.
+    pub fn set_priority(&self, priority: u32) {
+        if let Some(session) = &self.session {
+            session.set_priority(priority);
+        }
+    }
+
+    // Run a simulation from the current search root.
+    pub(super) fn search_once(options: &MCTSOptions, tree: &Tree, rng: &mut impl Rng) {
+        let root = tree.search_root();
+        let Some(found) = root.found(tree) else {
+            return;
+        };
+        // Search in the orientation of the tree.
+        let mut state = found.state;
+        let reward = Self::simulate_once(options, tree, found.node, &mut state, 0, false, rng);
+        // The subtree searched is consistent either way, but a stale
+        // result would skew the statistics of the new root's ancestors.
+        if tree.is_current(root.epoch) {
+            tree.propagate_reward(reward, &state.pieces);
+        }
+    }
+
+    /// Analysis of the current state, with the top `top_moves` candidates.
+    pub fn analysis(&self, top_moves: usize) -> Option<Analysis> {
+        analysis::analyze(&self.tree.load(), &self.cur_state(), top_moves, 10)
+    }
+
+    /// The `k` best candidate moves of the current state, each with its
+    /// principal variation, visit share, win rate and proven result.
+    pub fn multi_pv(&self, k: usize) -> Vec<MoveInfo> {
+        self.analysis(k).map(|a| a.moves).unwrap_or_default()
+    }
+
+    /// Periodically receive the analysis of the current state while the
+    /// background threads keep searching, without any moves until the search
+    /// reaches it. Drop the receiver to unsubscribe; the subscription also
+    /// ends when the engine is dropped.
+    pub fn subscribe_analysis(&self, options: AnalysisOptions) -> Receiver<Analysis> {
+        analysis::subscribe(
+            Arc::clone(&self.tree),
+            Arc::clone(&self.ponder),
+            Arc::clone(&self.stop),
+            options,
+        )
+    }
+
     pub fn set_timeout(&mut self, timeout: Duration) {
         self.time_out = timeout;
     }
 
-    fn rollout(options: &MCTSOptions, state: &State) -> i32 {
-        RolloutPolicy::rollout(options, state)
+    fn rollout(options: &MCTSOptions, state: &State, rng: &mut impl Rng) -> i32 {
+        RolloutPolicy::rollout(options, state, rng)
     }
 
     fn simulate_once(
         options: &MCTSOptions,
+        tree: &Tree,
         node: &Node,
         state: &mut State,
+        depth: u32,
         mut force_rollout: bool,
+        rng: &mut impl Rng,
     ) -> i32 {
         let winner = node.winner.load(Relaxed);
         if winner != 0 {
             return winner;
         }
+        if node.is_proven_draw() {
+            return 0;
+        }
         node.pre_update_stats();
 
         if force_rollout {
-            return node.update_stats(Self::rollout(options, state));
+            return node.update_stats(Self::rollout(options, state, rng));
         }
 
-        let expansion = match node.expansion.get() {
-            Some(expansion) => expansion,
-            None => {
-                // This is a leaf node.
-                if node.visits.load(SeqCst) <= options.rollouts_before_expanding {
-                    // Just rollout from here.
-                    return node.update_stats(Self::rollout(options, state));
+        if !node.is_expanded() {
+            // This is a leaf node.
+            if node.visits.load(SeqCst) <= options.rollouts_before_expanding {
+                // Just rollout from here.
+                return node.update_stats(Self::rollout(options, state, rng));
+            }
+            // Check for terminal node.
+            match Gomoku::get_winner(state) {
+                Some(Winner::PlayerJustMoved) => return node.update_stats(WIN),
+                Some(Winner::PlayerToMove) => return node.update_stats(LOSS),
+                Some(Winner::Draw) => {
+                    node.prove(0);
+                    return node.update_stats(0);
                 }
-                // Check for terminal node.
-                match Gomoku::get_winner(state) {
-                    Some(Winner::PlayerJustMoved) => return node.update_stats(WIN),
-                    Some(Winner::PlayerToMove) => return node.update_stats(LOSS),
-                    Some(Winner::Draw) => return node.update_stats(0),
-                    _ => {}
+                _ => {}
+            }
+            // Expand this node, and force a rollout when we recurse.
+            let collapse_symmetries = state.pieces.len() < options.symmetry_ply;
+            let children = match &options.progressive_widening {
+                Some(widening) => {
+                    let initial_children = widening.num_children(node.visits.load(Relaxed));
+                    tree.widen(node, state, collapse_symmetries, initial_children, false)
                 }
-                // Expand this node, and force a rollout when we recurse.
-                force_rollout = true;
-                node.expansion.try_set(new_expansion(state))
+                None => tree.expand(node, state, collapse_symmetries, false),
+            };
+            if children.is_none() {
+                // The tree is full, keep rolling out from here.
+                return node.update_stats(Self::rollout(options, state, rng));
             }
-        };
+            force_rollout = true;
+        } else if let Some(widening) = &options.progressive_widening {
+            let children = node.children(&tree.arena).unwrap();
+            // Moves proven to lose don't count, they won't be searched.
+            if !children.is_complete()
+                && children
+                    .iter()
+                    .filter(|n| n.winner.load(Relaxed) != LOSS)
+                    .count()
+                    < widening.num_children(node.visits.load(Relaxed))
+            {
+                let collapse_symmetries = state.pieces.len() < options.symmetry_ply;
+                tree.widen(node, state, collapse_symmetries, 0, false);
+            }
+        }
 
         // Recurse.
-        let next = match node.best_child(1.) {
+        let next = match if depth == 0 && options.multi_pv > 1 {
+            node.balanced_child(&tree.arena, options, options.multi_pv, rng)
+        } else {
+            node.best_child(&tree.arena, options, rng)
+        } {
             Some(child) => child,
             // TODO: Weird race condition?
             None => return 0,
         };
+        let child_bounds = next.bounds();
         let m = next.m.as_ref().unwrap();
         Gomoku::apply(state, m);
-        let child_result = Self::simulate_once(options, next, state, force_rollout);
+        let child_result =
+            Self::simulate_once(options, tree, next, state, depth + 1, force_rollout, rng);
         Gomoku::undo(state, m);
 
-        // Propagate up forced wins and losses.
-        let result = if child_result == WIN {
-            // Having a guaranteed win child makes you a loser parent.
-            LOSS
-        } else if child_result == LOSS {
-            // Having all guaranteed loser children makes you a winner parent.
-            if expansion
-                .children
-                .iter()
-                .all(|node| node.winner.load(Relaxed) == LOSS)
-            {
-                WIN
-            } else {
-                -1
-            }
-        } else {
-            -child_result
+        // Propagate up proven results.
+        if next.bounds() != child_bounds {
+            node.update_bounds(&tree.arena);
+        }
+        let result = match node.proven_value() {
+            Some(1) => WIN,
+            Some(-1) => LOSS,
+            // The opponent picked a move proven to lose, which they won't do.
+            _ if child_result == LOSS => -1,
+            _ => -child_result,
         };
 
         // Backpropagate.
         node.update_stats(result)
     }
 }
+
+// The background threads stop once the engine is dropped.
+impl Drop for MonteCarloTreeSearchContinue {
+    fn drop(&mut self) {
+        self.stop.store(true, Relaxed);
+    }
+}
+
+impl Strategy<Gomoku> for MonteCarloTreeSearchContinue {
+    fn choose_move(&mut self, state: &State) -> Option<Move> {
+        MonteCarloTreeSearchContinue::choose_move(self, state)
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) {
+        MonteCarloTreeSearchContinue::set_timeout(self, timeout)
+    }
+
+    fn principal_variation(&self) -> Vec<Move> {
+        let info = self.multi_pv(1).into_iter().next();
+        info.map(|info| info.pv).unwrap_or_default()
+    }
+
+    fn resigns(&self) -> bool {
+        MonteCarloTreeSearchContinue::resigns(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn search(seed: u64, state: &State) -> (Option<Move>, u32) {
+        let options = MCTSOptions::default()
+            .without_opening_book()
+            .with_seed(seed)
+            .with_playouts(2000);
+        let mut mcts = MonteCarloTreeSearchContinue::new(options);
+        mcts.start_simulating();
+        let m = mcts.choose_move(state);
+        (m, mcts.tree.load().root.visits.load(Relaxed))
+    }
+
+    #[test]
+    fn test_seeded_search() {
+        let state = State::new(vec![Move::new(7, 7), Move::new(6, 8), Move::new(8, 8)]);
+        let (m, visits) = search(42, &state);
+        assert_eq!(search(42, &state), (m, visits));
+        assert_eq!(m, Some(Move::new(5, 8)));
+
+        // Black completes five in a row.
+        let state = State::new(vec![
+            Move::new(7, 3),
+            Move::new(8, 3),
+            Move::new(7, 4),
+            Move::new(8, 4),
+            Move::new(7, 5),
+            Move::new(8, 5),
+            Move::new(7, 6),
+            Move::new(0, 0),
+        ]);
+        assert_eq!(search(1, &state).0, Some(Move::new(7, 2)));
+    }
+
+    #[test]
+    fn test_max_memory() {
+        let chunk = CHUNK_SIZE * std::mem::size_of::<Node>();
+        let options = MCTSOptions::default().with_max_memory(11 * chunk + chunk / 2);
+        assert_eq!(options.max_nodes, Some(8 * CHUNK_SIZE));
+        let options = MCTSOptions::default().with_max_memory(chunk / 2);
+        assert_eq!(options.max_nodes, Some(0));
+    }
+
+    #[test]
+    fn test_pruning() {
+        let options = MCTSOptions::default()
+            .without_opening_book()
+            .with_seed(1)
+            .with_playouts(1000)
+            .with_max_nodes(5000);
+        let mut mcts = MonteCarloTreeSearchContinue::new(options);
+        let mut state = State::default();
+        for _ in 0..6 {
+            let m = mcts.choose_move(&state).unwrap();
+            Gomoku::apply(&mut state, &m);
+        }
+        mcts.choose_move(&state);
+        // The tree was pruned, and keeps growing below the current state.
+        let tree = mcts.tree.load();
+        assert!(tree.root.visits.load(Relaxed) < 7000);
+        assert!(tree.find(&state.pieces).unwrap().node.is_expanded());
+    }
+
+    #[test]
+    fn test_rule_change() {
+        use crate::gomoku::Rule;
+
+        let options = MCTSOptions::default()
+            .without_opening_book()
+            .with_seed(1)
+            .with_playouts(200);
+        let mut mcts = MonteCarloTreeSearchContinue::new(options);
+        let state = State::new(vec![Move::new(7, 7)]);
+        mcts.choose_move(&state);
+        assert!(mcts.analysis(1).unwrap().visits > 0);
+        // The statistics of freestyle don't hold for standard.
+        mcts.change_cur_state(&state.with_rule(Rule::Standard));
+        assert_eq!(mcts.analysis(1).unwrap().visits, 0);
+    }
+
+    #[test]
+    fn test_subscribe_while_pondering() {
+        let options = MCTSOptions::default()
+            .without_opening_book()
+            .with_seed(1)
+            .with_playouts(1000)
+            .ponder();
+        let mut mcts = MonteCarloTreeSearchContinue::new(options);
+        let analyses = mcts
+            .subscribe_analysis(AnalysisOptions::default().with_interval(Duration::from_millis(5)));
+        let mut state = State::new(vec![Move::new(7, 7), Move::new(6, 8)]);
+        let m = mcts.choose_move(&state).unwrap();
+        Gomoku::apply(&mut state, &m);
+        assert_ne!(mcts.tree.load().search_root().state.pieces, state.pieces);
+        // The opponent's move is analysed, not the predicted reply searched.
+        let analysis = analyses
+            .iter()
+            .find(|analysis| analysis.state.pieces.len() >= state.pieces.len())
+            .unwrap();
+        assert_eq!(analysis.state.pieces, state.pieces);
+        assert!(!analysis.moves.is_empty());
+    }
+
+    #[test]
+    fn test_multi_pv() {
+        let state = State::new(vec![Move::new(7, 7), Move::new(6, 8)]);
+        let top_visits = |multi_pv| {
+            let options = MCTSOptions::default()
+                .without_opening_book()
+                .with_seed(1)
+                .with_playouts(3000)
+                .with_exploration(0.2)
+                .with_multi_pv(multi_pv);
+            let mut mcts = MonteCarloTreeSearchContinue::new(options);
+            mcts.choose_move(&state);
+            let moves = mcts.multi_pv(3);
+            assert_eq!(moves.len(), 3);
+            for (i, info) in moves.iter().enumerate() {
+                assert!(moves[..i].iter().all(|other| other.m != info.m));
+                assert!(moves[..i].iter().all(|other| other.visits >= info.visits));
+            }
+            moves.iter().map(|info| info.visits).collect::<Vec<_>>()
+        };
+        let focused = top_visits(1);
+        let balanced = top_visits(3);
+        // The balanced search spreads the visits evenly between the top 3.
+        assert!(balanced[0] - balanced[2] <= 2);
+        assert!(focused[0] - focused[2] > 2);
+    }
+
+    #[test]
+    fn test_take_back() {
+        let options = MCTSOptions::default()
+            .without_opening_book()
+            .with_seed(3)
+            .with_playouts(500);
+        let mut mcts = MonteCarloTreeSearchContinue::new(options);
+        let state = State::new(vec![Move::new(7, 7), Move::new(6, 8)]);
+        let m = mcts.choose_move(&state).unwrap();
+        let visits = mcts.analysis(1).unwrap().visits;
+
+        let mut next = state.clone();
+        Gomoku::apply(&mut next, &m);
+        mcts.choose_move(&next);
+        assert_eq!(mcts.take_back(1).pieces, state.pieces);
+        let analysis = mcts.analysis(225).unwrap();
+        assert!(analysis.visits >= visits + 500);
+        let info = analysis.moves.iter().find(|info| info.m == m).unwrap();
+        assert!(info.visits >= 500);
+
+        assert!(mcts.take_back(5).pieces.is_empty());
+        assert!(mcts.analysis(1).unwrap().visits >= analysis.visits);
+    }
+
+    #[test]
+    fn test_resignation() {
+        // White is to move on a nearly full board without any five points in
+        // a row left for white, and black has an open four.
+        let diagram = (0..15)
+            .map(|row| {
+                (0..15)
+                    .map(|col| match (row, col) {
+                        (7, 5) | (7, 10) | (0 | 14, 0 | 1) => '_',
+                        (7, 6..=9) => 'X',
+                        _ if (col / 2 + row) % 2 == 0 => 'X',
+                        _ => 'O',
+                    })
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut state = State::from_diagram(&diagram).unwrap();
+        let options = MCTSOptions::default()
+            .without_opening_book()
+            .with_seed(1)
+            .with_playouts(1000);
+        let mut mcts = MonteCarloTreeSearchContinue::new(options.clone());
+        mcts.choose_move(&state);
+        assert!(!mcts.resigns());
+        let mut mcts =
+            MonteCarloTreeSearchContinue::new(options.with_resignation(Resignation::default()));
+        mcts.choose_move(&state);
+        assert!(mcts.resigns());
+        assert!(mcts.accepts_draw(Stone::White));
+
+        Gomoku::apply(&mut state, &Move::new(7, 5));
+        assert_eq!(mcts.choose_move(&state), Some(Move::new(7, 10)));
+        assert!(!mcts.resigns());
+        assert!(!mcts.accepts_draw(Stone::Black));
+    }
+
+    #[test]
+    fn test_ponder() {
+        let options = MCTSOptions::default()
+            .without_opening_book()
+            .with_seed(1)
+            .with_playouts(1000)
+            .ponder();
+        let mut mcts = MonteCarloTreeSearchContinue::new(options);
+        let mut state = State::new(vec![Move::new(7, 7), Move::new(6, 8)]);
+        let m = mcts.choose_move(&state).unwrap();
+        Gomoku::apply(&mut state, &m);
+        mcts.change_cur_state(&state);
+        assert_eq!(mcts.cur_state().pieces, state.pieces);
+        let predicted = mcts
+            .tree
+            .load()
+            .search_root()
+            .state
+            .pieces
+            .last()
+            .copied()
+            .unwrap();
+        assert_eq!(mcts.ponder_result(), None);
+
+        let mut hit = state.clone();
+        Gomoku::apply(&mut hit, &predicted);
+        mcts.choose_move(&hit);
+        let result = mcts.ponder_result().unwrap();
+        assert_eq!(result.predicted, predicted);
+        assert!(result.hit);
+        assert!(result.playouts > 0);
+
+        // The opponent plays something else.
+        let state = mcts.tree.load().search_root().state.clone();
+        let mut miss = state.clone();
+        miss.pieces.pop();
+        let reply = (0..225)
+            .map(|i| Move::new(i / 15, i % 15))
+            .find(|m| !state.pieces.contains(m))
+            .unwrap();
+        Gomoku::apply(&mut miss, &reply);
+        mcts.change_cur_state(&miss);
+        assert!(!mcts.ponder_result().unwrap().hit);
+        assert_eq!(mcts.cur_state().pieces, miss.pieces);
+
+        // Taking back the reply is no reply at all.
+        let m = mcts.choose_move(&miss).unwrap();
+        let last = mcts.ponder_result();
+        Gomoku::apply(&mut miss, &m);
+        mcts.change_cur_state(&miss);
+        mcts.take_back(2);
+        assert_eq!(mcts.ponder_result(), last);
+        assert_eq!(mcts.cur_state().pieces.len(), miss.pieces.len() - 2);
+    }
+}
@@ -0,0 +1,237 @@
+use std::sync::atomic::{AtomicBool, Ordering::*};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+
+use crate::gomoku::{Move, State};
+
+use super::algorithm::Ponder;
+use super::node::Node;
+use super::tree::Tree;
+use super::{LOSS, WIN};
+
+/// A game result proven by the search, from the perspective of the player
+/// making the move.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Proof {
+    Win,
+    Draw,
+    Loss,
+}
+
+impl Proof {
+    fn of(node: &Node) -> Option<Self> {
+        match node.winner.load(Relaxed) {
+            WIN => Some(Proof::Win),
+            LOSS => Some(Proof::Loss),
+            _ if node.is_proven_draw() => Some(Proof::Draw),
+            _ => None,
+        }
+    }
+}
+
+/// Search statistics of a single candidate move.
+#[derive(Clone, Debug)]
+pub struct MoveInfo {
+    pub m: Move,
+    pub visits: u32,
+    /// Fraction of the current node's visits spent on this move.
+    pub visit_share: f64,
+    /// Normalized so all wins is 1.0, all draws is 0.5, and all losses is 0.0.
+    pub win_rate: f64,
+    /// Set if the search has proven the outcome of this move.
+    pub proven: Option<Proof>,
+    /// The principal variation, starting with `m`.
+    pub pv: Vec<Move>,
+}
+
+/// How pondering on the opponent's predicted reply went, see
+/// `MCTSOptions::ponder`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PonderResult {
+    /// The reply the engine pondered on.
+    pub predicted: Move,
+    /// Whether the opponent played it.
+    pub hit: bool,
+    /// Visits of the position the opponent's reply led to, which carry over
+    /// to the search of the engine's next move.
+    pub playouts: u32,
+}
+
+/// A snapshot of the search at the current state.
+#[derive(Clone, Debug)]
+pub struct Analysis {
+    /// The state being analysed.
+    pub state: State,
+    /// Visits of the current node.
+    pub visits: u32,
+    /// Visits of the whole tree.
+    pub total_visits: u32,
+    /// Candidate moves, most visited first.
+    pub moves: Vec<MoveInfo>,
+}
+
+/// Options for analysis subscriptions.
+#[derive(Clone)]
+pub struct AnalysisOptions {
+    interval: Duration,
+    top_moves: usize,
+    pv_length: usize,
+}
+
+impl Default for AnalysisOptions {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(500),
+            top_moves: 5,
+            pv_length: 10,
+        }
+    }
+}
+
+impl AnalysisOptions {
+    /// How often to emit an analysis. Defaults to 500ms.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// How many candidate moves to report. Defaults to 5.
+    pub fn with_top_moves(mut self, top_moves: usize) -> Self {
+        self.top_moves = top_moves;
+        self
+    }
+
+    /// Maximum length of each principal variation. Defaults to 10.
+    pub fn with_pv_length(mut self, pv_length: usize) -> Self {
+        self.pv_length = pv_length;
+        self
+    }
+}
+
+pub(super) fn analyze(
+    tree: &Tree,
+    state: &State,
+    top_moves: usize,
+    pv_length: usize,
+) -> Option<Analysis> {
+    let found = tree.find(&state.pieces)?;
+    let node = found.node;
+    // Report moves in the orientation of `state`.
+    let inverse = found.symmetry.inverse();
+    let mut children = node
+        .children(&tree.arena)
+        .map(|children| children.iter().collect::<Vec<_>>())
+        .unwrap_or_default();
+    // Proven wins first, then sort moves by visit count, largest first.
+    children.sort_by_key(|n| (n.winner.load(Relaxed) != WIN, !n.visits.load(Relaxed)));
+
+    let node_visits = node.visits.load(Relaxed).max(1) as f64;
+    let moves = children
+        .into_iter()
+        .filter(|n| n.visits.load(Relaxed) > 0)
+        .take(top_moves)
+        .map(|n| {
+            let m = n.m.unwrap().transform(inverse);
+            let mut pv = vec![m];
+            pv.extend(
+                n.principal_variation(&tree.arena, pv_length.saturating_sub(1))
+                    .into_iter()
+                    .map(|m| m.transform(inverse)),
+            );
+            let visits = n.visits.load(Relaxed);
+            MoveInfo {
+                m,
+                visits,
+                visit_share: visits as f64 / node_visits,
+                win_rate: n.win_rate(),
+                proven: Proof::of(n),
+                pv,
+            }
+        })
+        .collect();
+
+    Some(Analysis {
+        state: state.clone(),
+        visits: node.visits.load(Relaxed),
+        total_visits: tree.root.visits.load(Relaxed),
+        moves,
+    })
+}
+
+// Periodically sends the analysis of the current state until the receiver
+// hangs up or `stop` is set, when the engine is dropped.
+pub(super) fn subscribe(
+    tree: Arc<ArcSwap<Tree>>,
+    ponder: Arc<Mutex<Ponder>>,
+    stop: Arc<AtomicBool>,
+    options: AnalysisOptions,
+) -> Receiver<Analysis> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || loop {
+        thread::sleep(options.interval);
+        if stop.load(Relaxed) {
+            break;
+        }
+        let tree = tree.load();
+        let state = ponder.lock().unwrap().cur_state(&tree);
+        // A state not in the tree yet has no statistics, but is still sent,
+        // as sending is the only way to notice the receiver hung up.
+        let analysis =
+            analyze(&tree, &state, options.top_moves, options.pv_length).unwrap_or_else(|| {
+                Analysis {
+                    state,
+                    visits: 0,
+                    total_visits: tree.root.visits.load(Relaxed),
+                    moves: Vec::new(),
+                }
+            });
+        if sender.send(analysis).is_err() {
+            break;
+        }
+    });
+    receiver
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_subscribe() {
+        let tree = Arc::new(ArcSwap::from_pointee(Tree::new(None)));
+        let stop = Arc::new(AtomicBool::new(false));
+        let options = AnalysisOptions::default().with_interval(Duration::from_millis(1));
+        let start = || {
+            let ponder = Arc::default();
+            subscribe(
+                Arc::clone(&tree),
+                ponder,
+                Arc::clone(&stop),
+                options.clone(),
+            )
+        };
+
+        let analyses = start();
+        let analysis = analyses.recv().unwrap();
+        assert!(analysis.state.pieces.is_empty());
+        assert_eq!(analysis.visits, 0);
+        // The thread lets go of the tree once the receiver hangs up.
+        drop(analyses);
+        for _ in 0..1000 {
+            if Arc::strong_count(&tree) == 1 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(Arc::strong_count(&tree), 1);
+
+        // Or once the engine is dropped.
+        let analyses = start();
+        stop.store(true, Relaxed);
+        assert!(analyses.iter().count() <= 1);
+    }
+}
@@ -0,0 +1,170 @@
+//! A concurrent, append-only arena.
+//!
+//! Values are allocated in runs of consecutive indices, so the children of a
+//! tree node can be referenced by the index of the first child and their
+//! count. Allocation is a single compare-and-swap in the common case, and
+//! the whole arena is freed at once, a chunk at a time.
+
+use std::mem::{needs_drop, MaybeUninit};
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering::*};
+
+const CHUNK_BITS: usize = 16;
+/// Number of values per chunk. Runs never span two chunks, so this is also
+/// the longest run that can be allocated.
+pub const CHUNK_SIZE: usize = 1 << CHUNK_BITS;
+const MAX_CHUNKS: usize = 1 << 12;
+
+pub struct Arena<T> {
+    // Lazily allocated chunks of CHUNK_SIZE values.
+    chunks: Box<[AtomicPtr<MaybeUninit<T>>]>,
+    // Index of the next free value.
+    next: AtomicUsize,
+}
+
+impl<T> Default for Arena<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Arena<T> {
+    pub fn new() -> Self {
+        // Values are never dropped individually.
+        assert!(!needs_drop::<T>(), "arena values must not need drop");
+        Self {
+            chunks: (0..MAX_CHUNKS).map(|_| AtomicPtr::default()).collect(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Number of values allocated so far, including the ones skipped at the
+    /// end of each chunk.
+    pub fn len(&self) -> usize {
+        self.next.load(Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Memory reserved by the arena, in bytes.
+    pub fn memory(&self) -> usize {
+        let chunks = self
+            .chunks
+            .iter()
+            .filter(|chunk| !chunk.load(Relaxed).is_null())
+            .count();
+        chunks * CHUNK_SIZE * std::mem::size_of::<T>()
+    }
+
+    /// Allocate the values in consecutive indices, and return the first one.
+    ///
+    /// The values can be read back with `get` by whoever the index is
+    /// published to, e.g. through an atomic with Release ordering.
+    pub fn alloc(&self, values: impl ExactSizeIterator<Item = T>) -> usize {
+        let len = values.len();
+        assert!(len <= CHUNK_SIZE, "run doesn't fit in a chunk");
+        if len == 0 {
+            return 0;
+        }
+        let mut next = self.next.load(Relaxed);
+        let start = loop {
+            let mut start = next;
+            if start % CHUNK_SIZE + len > CHUNK_SIZE {
+                // Skip the end of this chunk, the run doesn't fit.
+                start = start.next_multiple_of(CHUNK_SIZE);
+            }
+            match self
+                .next
+                .compare_exchange_weak(next, start + len, Relaxed, Relaxed)
+            {
+                Ok(_) => break start,
+                Err(current) => next = current,
+            }
+        };
+        assert!(start / CHUNK_SIZE < MAX_CHUNKS, "arena is full");
+
+        let chunk = self.chunk(start / CHUNK_SIZE);
+        for (i, value) in values.enumerate() {
+            // Nobody else can access this run until its index is published.
+            unsafe { (*chunk.add(start % CHUNK_SIZE + i)).write(value) };
+        }
+        start
+    }
+
+    /// The run of `len` values starting at `start`, as returned by `alloc`.
+    pub fn get(&self, start: usize, len: usize) -> &[T] {
+        if len == 0 {
+            return &[];
+        }
+        assert!(start % CHUNK_SIZE + len <= CHUNK_SIZE && start + len <= self.len());
+        let chunk = self.chunks[start / CHUNK_SIZE].load(Acquire);
+        assert!(!chunk.is_null());
+        // The run was initialized by alloc, and is never written again.
+        unsafe { std::slice::from_raw_parts(chunk.add(start % CHUNK_SIZE) as *const T, len) }
+    }
+
+    /// The index of a value of this arena, as accepted by `get`.
+    pub fn index_of(&self, value: &T) -> Option<usize> {
+        let address = value as *const T as usize;
+        let chunk_bytes = CHUNK_SIZE * std::mem::size_of::<T>();
+        (0..self.len().div_ceil(CHUNK_SIZE)).find_map(|i| {
+            let chunk = self.chunks[i].load(Acquire) as usize;
+            let offset = address.checked_sub(chunk)?;
+            (chunk != 0 && offset < chunk_bytes)
+                .then(|| i * CHUNK_SIZE + offset / std::mem::size_of::<T>())
+        })
+    }
+
+    fn chunk(&self, index: usize) -> *mut MaybeUninit<T> {
+        let chunk = self.chunks[index].load(Acquire);
+        if !chunk.is_null() {
+            return chunk;
+        }
+        let new_chunk =
+            Box::into_raw(Box::<[T]>::new_uninit_slice(CHUNK_SIZE)) as *mut MaybeUninit<T>;
+        match self.chunks[index].compare_exchange(ptr::null_mut(), new_chunk, AcqRel, Acquire) {
+            Ok(_) => new_chunk,
+            Err(chunk) => {
+                // Someone beat us to it, drop ours.
+                unsafe { free_chunk(new_chunk) };
+                chunk
+            }
+        }
+    }
+}
+
+unsafe fn free_chunk<T>(chunk: *mut MaybeUninit<T>) {
+    drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+        chunk, CHUNK_SIZE,
+    )));
+}
+
+impl<T> Drop for Arena<T> {
+    fn drop(&mut self) {
+        for chunk in self.chunks.iter_mut() {
+            let chunk = *chunk.get_mut();
+            if !chunk.is_null() {
+                unsafe { free_chunk(chunk) };
+            }
+        }
+    }
+}
+
+#[test]
+fn test_alloc() {
+    let arena = Arena::new();
+    let first = arena.alloc(0..10u32);
+    assert_eq!(arena.get(first, 10), (0..10).collect::<Vec<_>>());
+
+    // Runs never span two chunks.
+    let second = arena.alloc(0..(CHUNK_SIZE as u32 - 5));
+    assert_eq!(second, CHUNK_SIZE);
+    assert_eq!(arena.get(second, 3), [0, 1, 2]);
+    assert_eq!(arena.get(first + 9, 1), [9]);
+    assert_eq!(arena.index_of(&arena.get(second, 3)[2]), Some(second + 2));
+    assert_eq!(arena.index_of(&9), None);
+    assert_eq!(arena.memory(), 2 * CHUNK_SIZE * 4);
+    assert_eq!(arena.get(arena.alloc(std::iter::empty()), 0), []);
+}
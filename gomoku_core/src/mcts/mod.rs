@@ -1,6 +1,12 @@
 pub mod algorithm;
-mod node;
+pub mod analysis;
+pub mod arena;
+pub mod move_selection;
+pub(crate) mod node;
+pub(crate) mod persistence;
+pub mod pool;
 mod rollout_policy;
+pub(crate) mod tree;
 mod utils;
 
 const WIN: i32 = i32::MAX;
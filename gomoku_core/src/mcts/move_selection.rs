@@ -0,0 +1,192 @@
+use std::sync::atomic::Ordering::*;
+use std::time::Duration;
+
+use rand::{distributions::WeightedIndex, prelude::Distribution, Rng};
+
+use super::node::{Children, Node};
+use super::{LOSS, WIN};
+
+/// How the final move is chosen from the children of the current node.
+///
+/// Whatever the policy, a child proven to be a win is always preferred,
+/// children proven to be losses are only chosen if nothing else is left, and
+/// a child guaranteeing at least a draw is preferred over one expected to lose.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum MoveSelection {
+    /// The most visited child.
+    #[default]
+    MostVisits,
+    /// The child with the highest win rate among the ones visited at least
+    /// `min_visits` times. Falls back to the most visited child.
+    WinRate { min_visits: u32 },
+    /// The child with the highest lower confidence bound of its win rate.
+    LowerConfidenceBound,
+    /// Keep searching until the most visited child also has the highest win
+    /// rate, for at most `max_extra_time` after the timeout.
+    RobustMax { max_extra_time: Duration },
+    /// Sample a child with probability proportional to
+    /// `visits^(1 / temperature)`.
+    Proportional { temperature: f32 },
+}
+
+// Roughly a 95% confidence interval.
+const LCB_Z: f64 = 1.96;
+
+fn visits(node: &Node) -> u32 {
+    node.visits.load(Relaxed)
+}
+
+// Lower bound of the Wilson score interval, which stays sensible for few
+// visits and win rates close to 0 or 1.
+fn lower_confidence_bound(node: &Node) -> f64 {
+    let n = visits(node) as f64;
+    if n == 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    let p = node.win_rate();
+    let z2 = LCB_Z * LCB_Z;
+    let center = p + z2 / (2.0 * n);
+    let margin = LCB_Z * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt();
+    (center - margin) / (1.0 + z2 / n)
+}
+
+impl MoveSelection {
+    /// Whether the search should keep going before a move is selected.
+    pub(super) fn needs_more_search(&self, children: Children) -> bool {
+        let MoveSelection::RobustMax { .. } = self else {
+            return false;
+        };
+        if children.iter().any(|n| n.winner.load(Relaxed) == WIN) {
+            return false;
+        }
+        let candidates = candidates(children);
+        let most_visited = candidates.iter().max_by_key(|n| visits(n));
+        let best_value = candidates
+            .iter()
+            .filter(|n| visits(n) > 0)
+            .max_by(|a, b| a.win_rate().total_cmp(&b.win_rate()));
+        match (most_visited, best_value) {
+            (Some(a), Some(b)) => !std::ptr::eq(*a, *b),
+            _ => false,
+        }
+    }
+
+    pub(super) fn select<'a>(
+        &self,
+        children: Children<'a>,
+        rng: &mut impl Rng,
+    ) -> Option<&'a Node> {
+        if let Some(win) = children.iter().find(|n| n.winner.load(Relaxed) == WIN) {
+            return Some(win);
+        }
+        let candidates = candidates(children);
+        let most_visited = candidates.iter().copied().max_by_key(|n| visits(n));
+        let chosen = self.select_from(&candidates, most_visited, rng)?;
+        // Don't gamble on a move expected to lose when a draw is guaranteed.
+        if chosen.bounds().0 < 0 && chosen.win_rate() < 0.5 {
+            let guaranteed = candidates
+                .iter()
+                .copied()
+                .filter(|n| n.bounds().0 >= 0)
+                .max_by_key(|n| visits(n));
+            if guaranteed.is_some() {
+                return guaranteed;
+            }
+        }
+        Some(chosen)
+    }
+
+    fn select_from<'a>(
+        &self,
+        candidates: &[&'a Node],
+        most_visited: Option<&'a Node>,
+        rng: &mut impl Rng,
+    ) -> Option<&'a Node> {
+        match *self {
+            MoveSelection::MostVisits | MoveSelection::RobustMax { .. } => most_visited,
+            MoveSelection::WinRate { min_visits } => candidates
+                .iter()
+                .copied()
+                .filter(|n| visits(n) > 0 && visits(n) >= min_visits)
+                .max_by(|a, b| a.win_rate().total_cmp(&b.win_rate()))
+                .or(most_visited),
+            MoveSelection::LowerConfidenceBound => candidates
+                .iter()
+                .copied()
+                .filter(|n| visits(n) > 0)
+                .max_by(|a, b| lower_confidence_bound(a).total_cmp(&lower_confidence_bound(b)))
+                .or(most_visited),
+            MoveSelection::Proportional { temperature } => {
+                let max_visits = visits(most_visited?).max(1) as f64;
+                // Normalize by the maximum to keep the weights finite at low temperatures.
+                let weights = candidates
+                    .iter()
+                    .map(|n| (visits(n) as f64 / max_visits).powf(1.0 / temperature as f64));
+                match WeightedIndex::new(weights) {
+                    Ok(dist) => Some(candidates[dist.sample(rng)]),
+                    Err(_) => most_visited,
+                }
+            }
+        }
+    }
+}
+
+// Children not proven to be losses, or all of them if there are none.
+fn candidates(children: Children<'_>) -> Vec<&Node> {
+    let alive = children
+        .iter()
+        .filter(|n| n.winner.load(Relaxed) != LOSS)
+        .collect::<Vec<_>>();
+    if alive.is_empty() {
+        children.iter().collect()
+    } else {
+        alive
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gomoku::Move;
+    use crate::mcts::arena::Arena;
+
+    fn child(col: usize, visits: u32, score: i32) -> Node {
+        let node = Node::new(Some(Move::new(7, col)));
+        node.visits.store(visits, Relaxed);
+        node.score.store(score, Relaxed);
+        node
+    }
+
+    #[test]
+    fn test_select() {
+        let arena = Arena::new();
+        let start = arena.alloc([child(0, 100, 0), child(1, 20, 16), child(2, 5, 5)].into_iter());
+        let children = Node::new(None).try_set_children(&arena, start, 3, 3);
+        let chosen = |selection: MoveSelection| {
+            selection
+                .select(children, &mut rand::thread_rng())
+                .unwrap()
+                .m
+        };
+
+        assert_eq!(chosen(MoveSelection::MostVisits), Some(Move::new(7, 0)));
+        assert_eq!(
+            chosen(MoveSelection::WinRate { min_visits: 10 }),
+            Some(Move::new(7, 1))
+        );
+        assert_eq!(
+            chosen(MoveSelection::WinRate { min_visits: 1 }),
+            Some(Move::new(7, 2))
+        );
+        assert_eq!(
+            chosen(MoveSelection::LowerConfidenceBound),
+            Some(Move::new(7, 1))
+        );
+
+        children[2].winner.store(WIN, Relaxed);
+        assert_eq!(chosen(MoveSelection::MostVisits), Some(Move::new(7, 2)));
+        children[2].winner.store(0, Relaxed);
+        children[0].winner.store(LOSS, Relaxed);
+        assert_eq!(chosen(MoveSelection::MostVisits), Some(Move::new(7, 1)));
+    }
+}
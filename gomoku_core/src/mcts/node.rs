@@ -1,8 +1,19 @@
+use super::algorithm::{MCTSOptions, UctFormula};
+use super::arena::Arena;
 use super::utils::*;
 use super::{LOSS, WIN};
 use crate::gomoku::{Gomoku, Move, State};
 use crate::interface::Game;
-use std::sync::atomic::{AtomicI32, AtomicU32, Ordering::*};
+use rand::Rng;
+use std::ops::Index;
+use std::sync::atomic::{AtomicI32, AtomicI8, AtomicU32, AtomicU64, Ordering::*};
+
+// Value of Node::children until the node is expanded.
+const NOT_EXPANDED: u64 = u64::MAX;
+// Value of Node::next_segment for the last segment.
+const NO_SEGMENT: u32 = u32::MAX;
+// Segments double in length, so 9 of them hold at least 256 children.
+const MAX_SEGMENTS: usize = 9;
 
 pub struct Node {
     // The Move to get from the parent to here.
@@ -12,50 +23,373 @@ pub struct Node {
     // +1 for wins, -1 for losses, +0 for draws.
     // From perspective of the player that made this move.
     pub score: AtomicI32,
+    // Number of simulations that didn't end in a draw, i.e. the sum of the
+    // squared results. Used to estimate the variance of the score.
+    pub decisive: AtomicU32,
     // Lazily populated if this node guarantees a particular end state.
     // WIN for a guaranteed win, LOSS for a guaranteed loss.
-    // Not bothering with draws.
+    // Proven draws are only tracked by the bounds below.
     pub winner: AtomicI32,
-    // Lazily populated.
-    pub expansion: AtomicBox<NodeExpansion>,
+    // Bounds of the game theoretical value of this node: +1 for a win, -1
+    // for a loss, 0 for a draw. From perspective of the player that made this
+    // move. The value is proven once both bounds meet.
+    pub pessimistic: AtomicI8,
+    pub optimistic: AtomicI8,
+    // Lazily populated. The index of the first child in the arena in the
+    // upper bits, then the number of legal moves and the length of the first
+    // segment of children, 8 bits each.
+    children: AtomicU64,
+    // Children are allocated in segments as the node is widened. The first
+    // child of each segment holds the index of the next segment.
+    next_segment: AtomicU32,
+}
+
+// The children of a node, stored as segments in the arena. The first segment
+// may hold all the legal moves, or only the most promising ones, in which case
+// each following segment is twice as long as the previous one, apart from the
+// first and the last.
+#[derive(Clone, Copy)]
+pub struct Children<'a> {
+    segments: [&'a [Node]; MAX_SEGMENTS],
+    num_segments: usize,
+    len: usize,
+    num_moves: usize,
+}
+
+impl<'a> Children<'a> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    // Whether every legal move has a child.
+    pub fn is_complete(&self) -> bool {
+        self.len == self.num_moves
+    }
+
+    // Number of legal moves of the node, with or without a child.
+    pub fn num_moves(&self) -> usize {
+        self.num_moves
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &'a Node> + 'a {
+        let segments = self.segments;
+        segments
+            .into_iter()
+            .take(self.num_segments)
+            .flat_map(|segment| segment.iter())
+    }
+
+    pub fn get(&self, mut index: usize) -> Option<&'a Node> {
+        for segment in &self.segments[..self.num_segments] {
+            if index < segment.len() {
+                return Some(&segment[index]);
+            }
+            index -= segment.len();
+        }
+        None
+    }
+
+    // The length of the segment after the current ones.
+    pub fn next_segment_len(&self) -> usize {
+        let len = match self.num_segments {
+            1 => self.segments[0].len(),
+            n => 2 * self.segments[n - 1].len(),
+        };
+        len.min(self.num_moves - self.len)
+    }
 }
-pub struct NodeExpansion {
-    pub children: Vec<Node>,
+
+impl Index<usize> for Children<'_> {
+    type Output = Node;
+
+    fn index(&self, index: usize) -> &Node {
+        self.get(index).expect("child index out of bounds")
+    }
 }
 
-pub fn new_expansion(state: &State) -> Box<NodeExpansion> {
+// The legal moves, each of them becoming a child when expanding a node.
+// If `collapse_symmetries` is set, only one of the moves leading to symmetric
+// positions is kept.
+pub fn expansion_moves(state: &State, collapse_symmetries: bool) -> Vec<Move> {
     let mut moves = Vec::new();
     Gomoku::generate_moves(state, &mut moves);
-    let children = moves
-        .into_iter()
-        .map(|m| Node::new(Some(m)))
-        .collect::<Vec<_>>();
-    Box::new(NodeExpansion { children })
+    if collapse_symmetries {
+        let symmetries = state.symmetries();
+        if symmetries.len() > 1 {
+            // Keep the smallest move of each set of equivalent moves.
+            moves.retain(|m| {
+                symmetries
+                    .iter()
+                    .all(|symmetry| m.index() <= m.transform(*symmetry).index())
+            });
+        }
+    }
+    moves
+}
+
+// Like expansion_moves, but sorted by a quick heuristic, most promising first:
+// moves close to many stones, then moves close to the center.
+pub fn prioritized_moves(state: &State, collapse_symmetries: bool) -> Vec<Move> {
+    let mut moves = expansion_moves(state, collapse_symmetries);
+    let mut neighbours = [0u32; 225];
+    for stone in &state.pieces {
+        let (row, col) = stone.get_coord();
+        for r in row.saturating_sub(2)..(row + 3).min(15) {
+            for c in col.saturating_sub(2)..(col + 3).min(15) {
+                let distance = r.abs_diff(row).max(c.abs_diff(col));
+                neighbours[r * 15 + c] += 3 - distance as u32;
+            }
+        }
+    }
+    moves.sort_by_key(|m| {
+        let (row, col) = m.get_coord();
+        (
+            std::cmp::Reverse(neighbours[m.index() as usize]),
+            row.abs_diff(7) + col.abs_diff(7),
+            m.index(),
+        )
+    });
+    moves
 }
 
 impl Node {
     pub fn new(m: Option<Move>) -> Self {
         Node {
             m,
-            expansion: AtomicBox::default(),
+            children: AtomicU64::new(NOT_EXPANDED),
             visits: AtomicU32::new(0),
             score: AtomicI32::new(0),
+            decisive: AtomicU32::new(0),
             winner: AtomicI32::new(0),
+            pessimistic: AtomicI8::new(-1),
+            optimistic: AtomicI8::new(1),
+            next_segment: AtomicU32::new(NO_SEGMENT),
         }
     }
 
+    // A node with the move and statistics of this one, but no children.
+    pub fn copy(&self) -> Node {
+        let node = Node::new(self.m);
+        node.copy_stats(self);
+        node
+    }
+
+    // Overwrite the statistics and bounds of this node with the ones of `other`.
+    pub fn copy_stats(&self, other: &Node) {
+        self.visits.store(other.visits.load(Relaxed), Relaxed);
+        self.score.store(other.score.load(Relaxed), Relaxed);
+        self.decisive.store(other.decisive.load(Relaxed), Relaxed);
+        self.winner.store(other.winner.load(Relaxed), Relaxed);
+        let (pessimistic, optimistic) = other.bounds();
+        self.pessimistic.store(pessimistic, Relaxed);
+        self.optimistic.store(optimistic, Relaxed);
+    }
+
+    pub fn children<'a>(&self, arena: &'a Arena<Node>) -> Option<Children<'a>> {
+        let packed = self.children.load(Acquire);
+        if packed == NOT_EXPANDED {
+            return None;
+        }
+        let mut children = Children {
+            segments: [&[]; MAX_SEGMENTS],
+            num_segments: 1,
+            len: (packed & 0xFF) as usize,
+            num_moves: ((packed >> 8) & 0xFF) as usize,
+        };
+        children.segments[0] = arena.get((packed >> 16) as usize, children.len);
+        while let Some(head) = children.segments[children.num_segments - 1].first() {
+            let start = head.next_segment.load(Acquire);
+            if start == NO_SEGMENT {
+                break;
+            }
+            let len = children.next_segment_len();
+            children.segments[children.num_segments] = arena.get(start as usize, len);
+            children.num_segments += 1;
+            children.len += len;
+        }
+        Some(children)
+    }
+
+    pub fn is_expanded(&self) -> bool {
+        self.children.load(Relaxed) != NOT_EXPANDED
+    }
+
+    // Tries to set the first segment of children of this node to the `len`
+    // nodes allocated at `start` in the arena, out of `num_moves` legal moves,
+    // if it has none yet.
+    // Returns whatever children the node has.
+    pub fn try_set_children<'a>(
+        &self,
+        arena: &'a Arena<Node>,
+        start: usize,
+        len: usize,
+        num_moves: usize,
+    ) -> Children<'a> {
+        debug_assert!(len <= num_moves && num_moves <= 0xFF);
+        let children = ((start as u64) << 16) | ((num_moves as u64) << 8) | len as u64;
+        // If someone beat us to it, our children are left unused in the arena.
+        let _ = self
+            .children
+            .compare_exchange(NOT_EXPANDED, children, AcqRel, Acquire);
+        self.children(arena).unwrap()
+    }
+
+    // Tries to append the segment of `Children::next_segment_len` nodes
+    // allocated at `start` to `children`, the current children of this node,
+    // if nobody else did.
+    // Returns whatever children the node has.
+    pub fn try_add_segment<'a>(
+        &self,
+        arena: &'a Arena<Node>,
+        children: &Children<'a>,
+        start: usize,
+    ) -> Children<'a> {
+        let last = children.segments[children.num_segments - 1];
+        // If someone beat us to it, our segment is left unused in the arena.
+        let _ = last[0]
+            .next_segment
+            .compare_exchange(NO_SEGMENT, start as u32, AcqRel, Acquire);
+        self.children(arena).unwrap()
+    }
+
     // Choose best child based on UCT.
-    pub fn best_child(&self, exploration_score: f32) -> Option<&Node> {
+    pub fn best_child<'a>(
+        &self,
+        arena: &'a Arena<Node>,
+        options: &MCTSOptions,
+        rng: &mut impl Rng,
+    ) -> Option<&'a Node> {
         let mut log_visits = (self.visits.load(SeqCst) as f32).log2();
         // Keep this numerator non-negative.
         if log_visits < 0.0 {
             log_visits = 0.0;
         }
 
-        let expansion = self.expansion.get()?;
-        random_best(expansion.children.as_slice(), |node| {
-            node.uct_score(exploration_score, log_visits)
-        })
+        // The best pessimistic bound among the children is the negation of
+        // our optimistic bound.
+        let optimistic = self.optimistic.load(Relaxed);
+        let children = self.children(arena)?;
+        random_best(
+            children.len(),
+            move |i| children.get(i).unwrap(),
+            |node| node.uct_score(options, log_visits, optimistic),
+            rng,
+        )
+    }
+
+    // Normalized so all wins is 1.0, all draws is 0.5, and all losses is 0.0.
+    pub fn win_rate(&self) -> f64 {
+        let visits = self.visits.load(Relaxed) as f64;
+        let score = self.score.load(Relaxed) as f64;
+        if visits == 0.0 {
+            return 0.5;
+        }
+        (score + visits) / (visits * 2.0)
+    }
+
+    pub fn most_visited_child<'a>(&self, arena: &'a Arena<Node>) -> Option<&'a Node> {
+        self.children(arena)?
+            .iter()
+            .max_by_key(|n| n.visits.load(Relaxed))
+    }
+
+    // Follow the most visited children down the tree, up to max_len moves.
+    pub fn principal_variation(&self, arena: &Arena<Node>, max_len: usize) -> Vec<Move> {
+        let mut pv = Vec::new();
+        let mut node = self;
+        while pv.len() < max_len {
+            match node.most_visited_child(arena) {
+                Some(child) if child.visits.load(Relaxed) > 0 => {
+                    pv.push(child.m.unwrap());
+                    node = child;
+                }
+                _ => break,
+            }
+        }
+        pv
+    }
+
+    // Choose a child like best_child, but keep the search balanced between the
+    // `k` most visited children: whenever UCT picks one of them, the least
+    // visited one of them is searched instead.
+    pub fn balanced_child<'a>(
+        &self,
+        arena: &'a Arena<Node>,
+        options: &MCTSOptions,
+        k: usize,
+        rng: &mut impl Rng,
+    ) -> Option<&'a Node> {
+        let best = self.best_child(arena, options, rng)?;
+        if best.winner.load(Relaxed) == WIN {
+            return Some(best);
+        }
+        let mut top = self
+            .children(arena)?
+            .iter()
+            .filter(|n| n.winner.load(Relaxed) != LOSS)
+            .collect::<Vec<_>>();
+        if top.len() > k {
+            top.select_nth_unstable_by_key(k - 1, |n| !n.visits.load(Relaxed));
+            top.truncate(k);
+        }
+        if top.iter().any(|n| std::ptr::eq(*n, best)) {
+            top.into_iter().min_by_key(|n| n.visits.load(Relaxed))
+        } else {
+            Some(best)
+        }
+    }
+
+    pub fn bounds(&self) -> (i8, i8) {
+        (self.pessimistic.load(SeqCst), self.optimistic.load(SeqCst))
+    }
+
+    // The proven game theoretical value of this node, if any.
+    pub fn proven_value(&self) -> Option<i8> {
+        let (pessimistic, optimistic) = self.bounds();
+        (pessimistic == optimistic).then_some(pessimistic)
+    }
+
+    pub fn is_proven_draw(&self) -> bool {
+        self.proven_value() == Some(0)
+    }
+
+    // Mark this node as a terminal state with the given value.
+    pub fn prove(&self, value: i8) {
+        self.pessimistic.store(value, SeqCst);
+        self.optimistic.store(value, SeqCst);
+        match value {
+            1 => self.winner.store(WIN, SeqCst),
+            -1 => self.winner.store(LOSS, SeqCst),
+            _ => {}
+        }
+    }
+
+    // Recompute the bounds of this node from its children, which are moves of
+    // the opponent: they pick the child that is best for them.
+    // Returns whether the bounds changed.
+    pub fn update_bounds(&self, arena: &Arena<Node>) -> bool {
+        let Some(children) = self.children(arena) else {
+            return false;
+        };
+        let (mut best_pessimistic, mut best_optimistic) = (-1, -1);
+        if !children.is_complete() {
+            // The moves without a child yet could still be wins for the
+            // opponent.
+            best_optimistic = 1;
+        }
+        for child in children.iter() {
+            let (pessimistic, optimistic) = child.bounds();
+            best_pessimistic = best_pessimistic.max(pessimistic);
+            best_optimistic = best_optimistic.max(optimistic);
+        }
+        let (pessimistic, optimistic) = (-best_optimistic, -best_pessimistic);
+        // Bounds only ever tighten, so concurrent updates can't loosen them.
+        let old_pessimistic = self.pessimistic.fetch_max(pessimistic, SeqCst);
+        let old_optimistic = self.optimistic.fetch_min(optimistic, SeqCst);
+        if let Some(value) = self.proven_value() {
+            self.prove(value);
+        }
+        old_pessimistic < pessimistic || old_optimistic > optimistic
     }
 
     pub fn pre_update_stats(&self) {
@@ -67,45 +401,53 @@ impl Node {
 
     pub fn update_stats(&self, result: i32) -> i32 {
         if result == WIN || result == LOSS {
-            self.winner.store(result, SeqCst);
+            self.prove(if result == WIN { 1 } else { -1 });
         } else {
             // Adjust for virtual loss.
             self.score.fetch_add(result + 1, SeqCst);
+            if result != 0 {
+                self.decisive.fetch_add(1, SeqCst);
+            }
         }
         // Always return Some, as we aren't timed out.
         result
     }
 
-    pub fn get_to_node(&self, moves: &[Move]) -> Option<&Node> {
-        let mut res: &Node = self;
-        for m in moves {
-            let expansion = res.expansion.get()?;
-            for n in expansion.children.iter() {
-                if n.m? == *m {
-                    res = &n;
-                }
-            }
+    // Call `f` with the state before each move of the first `max_ply` moves
+    // of the tree and the child node of that move, for all children visited at
+    // least `min_visits` times.
+    pub fn for_each_edge(
+        &self,
+        arena: &Arena<Node>,
+        state: &mut State,
+        max_ply: usize,
+        min_visits: u32,
+        f: &mut impl FnMut(&State, &Node),
+    ) {
+        if state.pieces.len() >= max_ply {
+            return;
         }
-        Some(res)
-    }
-
-    pub fn propagate_reward(&self, reward: i32, moves: &[Move]) {
-        let mut res: &Node = self;
-        res.pre_update_stats();
-        res.update_stats(reward);
-        for m in moves {
-            let expansion = res.expansion.get().unwrap();
-            for n in expansion.children.iter() {
-                if n.m.unwrap() == *m {
-                    res = &n;
-                }
+        let Some(children) = self.children(arena) else {
+            return;
+        };
+        for child in children.iter() {
+            if child.visits.load(Relaxed) < min_visits.max(1) {
+                continue;
             }
-            res.pre_update_stats();
-            res.update_stats(reward);
+            f(state, child);
+            let m = child.m.unwrap();
+            Gomoku::apply(state, &m);
+            child.for_each_edge(arena, state, max_ply, min_visits, f);
+            Gomoku::undo(state, &m);
         }
     }
 
-    fn uct_score(&self, exploration_score: f32, log_parent_visits: f32) -> f32 {
+    fn uct_score(
+        &self,
+        options: &MCTSOptions,
+        log_parent_visits: f32,
+        parent_optimistic: i8,
+    ) -> f32 {
         let winner = self.winner.load(Relaxed);
         if winner < 0 {
             // Large enough to be returned from best_move, smaller than any other value.
@@ -118,9 +460,23 @@ impl Node {
         if winner > 0 {
             return f32::INFINITY;
         }
+        let (pessimistic, optimistic) = self.bounds();
+        if pessimistic == optimistic {
+            // A proven draw has an exact value, there is nothing to explore.
+            return 0.5;
+        }
+        if optimistic < -parent_optimistic {
+            // A sibling is proven to be at least as good as the best this
+            // move could be, so it is effectively a loss.
+            return -1.0;
+        }
+        let exploration_score = options.exploration;
         let visits = self.visits.load(Relaxed) as f32;
         let score = self.score.load(Relaxed) as f32;
         if visits == 0.0 {
+            if let Some(urgency) = options.first_play_urgency {
+                return urgency;
+            }
             // Avoid NaNs.
             return if exploration_score > 0.0 {
                 f32::INFINITY
@@ -128,7 +484,102 @@ impl Node {
                 0.0
             };
         }
-        let win_ratio = (score + visits) / (2.0 * visits);
-        win_ratio + exploration_score * (2.0 * log_parent_visits / visits).sqrt()
+        // Keep the estimate within the proven bounds.
+        let win_ratio = ((score + visits) / (2.0 * visits)).clamp(
+            (pessimistic as f32 + 1.0) / 2.0,
+            (optimistic as f32 + 1.0) / 2.0,
+        );
+        match options.uct_formula {
+            UctFormula::Ucb1 => {
+                win_ratio + exploration_score * (2.0 * log_parent_visits / visits).sqrt()
+            }
+            UctFormula::Ucb1Tuned => {
+                // Results are mapped to 0, 1/2 and 1, so the mean of the
+                // squares is (decisive + 2 * score + visits) / (4 * visits).
+                let decisive = self.decisive.load(Relaxed) as f32;
+                let square_mean = (decisive + 2.0 * score + visits) / (4.0 * visits);
+                let variance = (square_mean - win_ratio * win_ratio).max(0.0)
+                    + (2.0 * log_parent_visits / visits).sqrt();
+                win_ratio
+                    + exploration_score * (log_parent_visits / visits * variance.min(0.25)).sqrt()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn expand(node: &Node, arena: &Arena<Node>, moves: impl ExactSizeIterator<Item = Move>) {
+        let len = moves.len();
+        let start = arena.alloc(moves.map(|m| Node::new(Some(m))));
+        node.try_set_children(arena, start, len, len);
+    }
+
+    #[test]
+    fn test_update_bounds() {
+        let arena = Arena::new();
+        let node = Node::new(None);
+        expand(&node, &arena, (0..3).map(|col| Move::new(0, col)));
+        let children = node.children(&arena).unwrap();
+
+        // The opponent can still win with any move.
+        assert!(!node.update_bounds(&arena));
+        assert_eq!(node.bounds(), (-1, 1));
+
+        // The opponent can't lose with the first move.
+        children[0].pessimistic.store(0, SeqCst);
+        assert!(node.update_bounds(&arena));
+        assert_eq!(node.bounds(), (-1, 0));
+
+        // All the opponent's moves draw or lose.
+        children[0].prove(0);
+        children[1].prove(-1);
+        children[2].prove(0);
+        assert!(node.update_bounds(&arena));
+        assert!(node.is_proven_draw());
+        assert_eq!(node.winner.load(SeqCst), 0);
+
+        // One winning move for the opponent is enough.
+        let node = Node::new(None);
+        expand(&node, &arena, (0..2).map(|col| Move::new(0, col)));
+        node.children(&arena).unwrap()[1].prove(1);
+        assert!(node.update_bounds(&arena));
+        assert_eq!(node.winner.load(SeqCst), LOSS);
+    }
+
+    #[test]
+    fn test_uct_score() {
+        // 5 wins, 4 draws and 1 loss: a win ratio of 0.7, and a mean of the
+        // squared results of 0.6.
+        let node = Node::new(Some(Move::new(7, 7)));
+        node.visits.store(10, SeqCst);
+        node.score.store(4, SeqCst);
+        node.decisive.store(6, SeqCst);
+        let score = |options: &MCTSOptions, log_parent_visits| {
+            node.uct_score(options, log_parent_visits, 1)
+        };
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-5;
+
+        let ucb1 = MCTSOptions::default();
+        assert!(close(score(&ucb1, 5.0), 0.7 + 1.0));
+        assert!(close(
+            score(&ucb1.clone().with_exploration(2.0), 5.0),
+            0.7 + 2.0
+        ));
+        let tuned = ucb1.with_uct_formula(UctFormula::Ucb1Tuned);
+        // The variance bound is capped at 1/4.
+        assert!(close(score(&tuned, 5.0), 0.7 + 0.125f32.sqrt()));
+        // A variance of 0.6 - 0.49 = 0.11, plus sqrt(2 * 0.05 / 10).
+        assert!(close(score(&tuned, 0.05), 0.7 + (0.005f32 * 0.21).sqrt()));
+
+        // Unvisited children are searched first, unless their urgency is set.
+        let unvisited = Node::new(Some(Move::new(7, 8)));
+        let options = MCTSOptions::default();
+        assert_eq!(unvisited.uct_score(&options, 5.0, 1), f32::INFINITY);
+        let options = options.with_first_play_urgency(0.8);
+        assert_eq!(unvisited.uct_score(&options, 5.0, 1), 0.8);
+        assert!(node.uct_score(&options, 0.0, 1) < 0.8);
     }
 }
@@ -0,0 +1,229 @@
+//! A versioned binary format for search trees.
+//!
+//! The file starts with the magic bytes `MCTS` and a little endian `u32`
+//! version, the rule of the tree as one byte (since version 2, version 1 trees
+//! are freestyle), followed by the root node. Each node is stored as
+//!
+//! - its move, one byte (`NO_MOVE` at the root),
+//! - visits, score, decisive count and winner,
+//! - its pessimistic and optimistic bounds,
+//! - the number of saved children as a `u16` (`NOT_EXPANDED` for leaves),
+//!   followed by the saved children.
+//!
+//! Children that were pruned are recreated empty when loading, as every
+//! expansion contains all the legal moves of its state.
+
+use std::collections::{BinaryHeap, HashSet};
+use std::io::{self, Read, Write};
+use std::sync::atomic::Ordering::*;
+
+use crate::gomoku::{Gomoku, Rule, State};
+use crate::interface::Game;
+use crate::io_util::{invalid_data, read_array, read_rule, rule_byte};
+
+use super::node::Node;
+use super::tree::Tree;
+
+const MAGIC: &[u8; 4] = b"MCTS";
+const VERSION: u32 = 2;
+const NO_MOVE: u8 = u8::MAX;
+const NOT_EXPANDED: u16 = u16::MAX;
+
+/// Write the tree, skipping the subtrees of nodes visited less than
+/// `min_visits` times, unless their result is (partially) proven. With
+/// `max_nodes`, only that many nodes are written, most visited first.
+pub(crate) fn save_tree<W: Write>(
+    tree: &Tree,
+    mut writer: W,
+    min_visits: u32,
+    max_nodes: Option<usize>,
+) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(&[rule_byte(tree.rule())])?;
+    match max_nodes {
+        Some(max_nodes) => {
+            let picked = most_visited(tree, min_visits, max_nodes);
+            let keep = |node: &Node| picked.contains(&(node as *const Node));
+            write_node(tree, &tree.root, &mut writer, &keep)?;
+        }
+        None => write_node(tree, &tree.root, &mut writer, &|node| {
+            keep(node, min_visits)
+        })?,
+    }
+    writer.flush()
+}
+
+fn keep(node: &Node, min_visits: u32) -> bool {
+    node.visits.load(Relaxed) >= min_visits.max(1) || node.bounds() != (-1, 1)
+}
+
+// The `max_nodes` most visited nodes among the ones to keep. They are picked
+// best first from the root, so the parent of every node picked is too.
+fn most_visited(tree: &Tree, min_visits: u32, max_nodes: usize) -> HashSet<*const Node> {
+    let mut nodes = vec![&tree.root];
+    let mut queue = BinaryHeap::from([(tree.root.visits.load(Relaxed), 0)]);
+    let mut picked = HashSet::new();
+    while let Some((_, index)) = queue.pop() {
+        // The root is always written.
+        if picked.len() >= max_nodes.max(1) {
+            break;
+        }
+        let node = nodes[index];
+        picked.insert(node as *const Node);
+        let Some(children) = node.children(&tree.arena) else {
+            continue;
+        };
+        for child in children.iter().filter(|child| keep(child, min_visits)) {
+            queue.push((child.visits.load(Relaxed), nodes.len()));
+            nodes.push(child);
+        }
+    }
+    picked
+}
+
+fn write_node<W: Write>(
+    tree: &Tree,
+    node: &Node,
+    writer: &mut W,
+    keep: &dyn Fn(&Node) -> bool,
+) -> io::Result<()> {
+    writer.write_all(&[node.m.map_or(NO_MOVE, |m| m.index())])?;
+    writer.write_all(&node.visits.load(Relaxed).to_le_bytes())?;
+    writer.write_all(&node.score.load(Relaxed).to_le_bytes())?;
+    writer.write_all(&node.decisive.load(Relaxed).to_le_bytes())?;
+    writer.write_all(&node.winner.load(Relaxed).to_le_bytes())?;
+    let (pessimistic, optimistic) = node.bounds();
+    writer.write_all(&[pessimistic as u8, optimistic as u8])?;
+
+    let Some(children) = node.children(&tree.arena) else {
+        return writer.write_all(&NOT_EXPANDED.to_le_bytes());
+    };
+    let children = children
+        .iter()
+        .filter(|child| keep(child))
+        .collect::<Vec<_>>();
+    writer.write_all(&(children.len() as u16).to_le_bytes())?;
+    for child in children {
+        write_node(tree, child, writer, keep)?;
+    }
+    Ok(())
+}
+
+/// Read a tree written by `save_tree`, rooted at the empty board.
+pub(crate) fn load_tree<R: Read>(mut reader: R, max_nodes: Option<usize>) -> io::Result<Tree> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("not a search tree file"));
+    }
+    let rule = match u32::from_le_bytes(read_array(&mut reader)?) {
+        1 => Rule::Freestyle,
+        VERSION => read_rule(&mut reader)?,
+        _ => return Err(invalid_data("unsupported search tree version")),
+    };
+
+    let tree = Tree::new(max_nodes);
+    tree.set_search_root(&State::default().with_rule(rule), 0);
+    if read_array::<1, _>(&mut reader)?[0] != NO_MOVE {
+        return Err(invalid_data("root node has a move"));
+    }
+    read_node(&tree, &tree.root, &mut reader, &mut State::default())?;
+    Ok(tree)
+}
+
+// Read the stats and children of `node`, whose move was already read.
+fn read_node<R: Read>(
+    tree: &Tree,
+    node: &Node,
+    reader: &mut R,
+    state: &mut State,
+) -> io::Result<()> {
+    node.visits
+        .store(u32::from_le_bytes(read_array(reader)?), Relaxed);
+    node.score
+        .store(i32::from_le_bytes(read_array(reader)?), Relaxed);
+    node.decisive
+        .store(u32::from_le_bytes(read_array(reader)?), Relaxed);
+    node.winner
+        .store(i32::from_le_bytes(read_array(reader)?), Relaxed);
+    let [pessimistic, optimistic] = read_array(reader)?;
+    node.pessimistic.store(pessimistic as i8, Relaxed);
+    node.optimistic.store(optimistic as i8, Relaxed);
+
+    let num_children = u16::from_le_bytes(read_array(reader)?);
+    if num_children == NOT_EXPANDED {
+        return Ok(());
+    }
+    let children = tree.expand(node, state, false, true).unwrap();
+    for _ in 0..num_children {
+        let [index] = read_array(reader)?;
+        let child = children
+            .iter()
+            .find(|child| child.m.map(|m| m.index()) == Some(index))
+            .ok_or_else(|| invalid_data("illegal move in search tree"))?;
+        let m = child.m.unwrap();
+        Gomoku::apply(state, &m);
+        read_node(tree, child, reader, state)?;
+        Gomoku::undo(state, &m);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let tree = Tree::new(None);
+        let children = tree
+            .expand(&tree.root, &State::default(), false, true)
+            .unwrap();
+        tree.root.visits.store(30, Relaxed);
+        for (i, child) in children.iter().take(3).enumerate() {
+            child.visits.store(10 - i as u32, Relaxed);
+            child.score.store(i as i32 - 1, Relaxed);
+        }
+        let first = &children[0];
+        tree.expand(first, &State::new(vec![first.m.unwrap()]), false, true);
+        children[4].prove(0);
+
+        let mut bytes = Vec::new();
+        save_tree(&tree, &mut bytes, 9, None).unwrap();
+        let loaded = load_tree(bytes.as_slice(), None).unwrap();
+
+        let children = loaded.root.children(&loaded.arena).unwrap();
+        assert_eq!(loaded.root.visits.load(Relaxed), 30);
+        assert_eq!(children.len(), 225);
+        assert_eq!(children[0].visits.load(Relaxed), 10);
+        assert_eq!(children[0].score.load(Relaxed), -1);
+        assert_eq!(children[0].children(&loaded.arena).unwrap().len(), 224);
+        assert_eq!(children[1].visits.load(Relaxed), 9);
+        // Pruned.
+        assert_eq!(children[2].visits.load(Relaxed), 0);
+        // Proven results are always kept.
+        assert!(children[4].is_proven_draw());
+
+        assert!(load_tree(&bytes[1..], None).is_err());
+
+        // Only the root and its two most visited children.
+        let mut capped = Vec::new();
+        save_tree(&tree, &mut capped, 9, Some(3)).unwrap();
+        let loaded = load_tree(capped.as_slice(), None).unwrap();
+        let children = loaded.root.children(&loaded.arena).unwrap();
+        assert_eq!(children[0].visits.load(Relaxed), 10);
+        assert_eq!(children[1].visits.load(Relaxed), 9);
+        assert!(!children[4].is_proven_draw());
+        assert!(capped.len() < bytes.len());
+
+        let tree = Tree::new(None);
+        tree.set_search_root(&State::default().with_rule(Rule::Standard), 0);
+        let mut bytes = Vec::new();
+        save_tree(&tree, &mut bytes, 1, None).unwrap();
+        assert_eq!(
+            load_tree(bytes.as_slice(), None).unwrap().rule(),
+            Rule::Standard
+        );
+    }
+}
@@ -0,0 +1,244 @@
+//! A pool of search threads shared by several engines.
+//!
+//! Each engine started with `MonteCarloTreeSearchContinue::start_simulating_in`
+//! is a session of the pool. The threads run playouts for the sessions in
+//! small batches, always picking the session that got the least playouts for
+//! its priority, so a session with priority 2 gets twice the playouts of one
+//! with priority 1, and the CPU is never oversubscribed however many engines
+//! are searching.
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering::*};
+use std::sync::{Arc, Mutex};
+
+use arc_swap::ArcSwap;
+use rand::{rngs::StdRng, SeedableRng};
+
+use super::algorithm::{MCTSOptions, MonteCarloTreeSearchContinue};
+use super::tree::Tree;
+
+// Playouts run for a session before picking the next one.
+const BATCH: u32 = 64;
+
+thread_local! {
+    // The random number generator of the unseeded sessions on this thread.
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+pub struct SearchPool {
+    threads: rayon::ThreadPool,
+    shared: Arc<Shared>,
+}
+
+// A search sharing the pool.
+pub(super) struct Session {
+    tree: Arc<ArcSwap<Tree>>,
+    options: MCTSOptions,
+    // Set once the engine is dropped.
+    stop: Arc<AtomicBool>,
+    priority: AtomicU32,
+    // Seeds the random number generator of each batch, if the session is seeded.
+    batches: AtomicU64,
+}
+
+impl Session {
+    pub(super) fn new(
+        tree: Arc<ArcSwap<Tree>>,
+        options: MCTSOptions,
+        stop: Arc<AtomicBool>,
+    ) -> Self {
+        Session {
+            tree,
+            options,
+            stop,
+            priority: AtomicU32::new(1),
+            batches: AtomicU64::new(0),
+        }
+    }
+
+    pub(super) fn set_priority(&self, priority: u32) {
+        self.priority.store(priority.max(1), Relaxed);
+    }
+}
+
+struct Shared {
+    scheduler: Mutex<Scheduler>,
+    // Set once the pool is dropped.
+    closed: AtomicBool,
+}
+
+#[derive(Default)]
+struct Scheduler {
+    // Each session with its playouts so far divided by its priority.
+    sessions: Vec<(Arc<Session>, f64)>,
+    // Threads without a batch to run.
+    idle: usize,
+}
+
+impl Scheduler {
+    // The session to run a batch for next, if any.
+    fn next(&mut self) -> Option<Arc<Session>> {
+        self.sessions
+            .retain(|(session, _)| !session.stop.load(Relaxed));
+        let (session, pass) = self
+            .sessions
+            .iter_mut()
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))?;
+        *pass += BATCH as f64 / session.priority.load(Relaxed) as f64;
+        Some(Arc::clone(session))
+    }
+
+    fn add(&mut self, session: Arc<Session>) {
+        // Start even with the others, rather than catching up with them.
+        let pass = self.sessions.iter().map(|(_, pass)| *pass).reduce(f64::min);
+        self.sessions.push((session, pass.unwrap_or(0.0)));
+    }
+}
+
+// Run a batch of playouts, then schedule the next one.
+fn work(shared: Arc<Shared>) {
+    let session = {
+        let mut scheduler = shared.scheduler.lock().unwrap();
+        match scheduler.next().filter(|_| !shared.closed.load(Relaxed)) {
+            Some(session) => session,
+            None => {
+                scheduler.idle += 1;
+                return;
+            }
+        }
+    };
+    let run = |rng: &mut StdRng| {
+        for _ in 0..BATCH {
+            if session.stop.load(Relaxed) {
+                break;
+            }
+            MonteCarloTreeSearchContinue::search_once(&session.options, &session.tree.load(), rng);
+        }
+    };
+    // A batch may run on any thread, so a seeded session can't use the
+    // thread's generator.
+    if session.options.is_seeded() {
+        run(&mut session.options.rng(session.batches.fetch_add(1, Relaxed)));
+    } else {
+        RNG.with(|rng| run(&mut rng.borrow_mut()));
+    }
+    rayon::spawn(move || work(shared));
+}
+
+impl SearchPool {
+    /// A pool of `threads` threads.
+    pub fn new(threads: usize) -> Self {
+        let threads = threads.max(1);
+        SearchPool {
+            threads: rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .thread_name(|i| format!("search-{i}"))
+                .build()
+                .expect("failed to start the search threads"),
+            shared: Arc::new(Shared {
+                scheduler: Mutex::new(Scheduler {
+                    sessions: Vec::new(),
+                    idle: threads,
+                }),
+                closed: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// Number of sessions still searching.
+    pub fn num_sessions(&self) -> usize {
+        let mut scheduler = self.shared.scheduler.lock().unwrap();
+        scheduler
+            .sessions
+            .retain(|(session, _)| !session.stop.load(Relaxed));
+        scheduler.sessions.len()
+    }
+
+    pub(super) fn add(&self, session: Arc<Session>) {
+        let mut scheduler = self.shared.scheduler.lock().unwrap();
+        scheduler.add(session);
+        // Wake up the idle threads.
+        for _ in 0..std::mem::take(&mut scheduler.idle) {
+            let shared = Arc::clone(&self.shared);
+            self.threads.spawn(move || work(shared));
+        }
+    }
+}
+
+/// A pool with a thread per CPU.
+impl Default for SearchPool {
+    fn default() -> Self {
+        Self::new(num_cpus::get())
+    }
+}
+
+// The threads finish their batch and exit.
+impl Drop for SearchPool {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_scheduler() {
+        let session = |priority| {
+            let tree = Arc::new(ArcSwap::from_pointee(Tree::new(None)));
+            let stop = Arc::new(AtomicBool::new(false));
+            let session = Arc::new(Session::new(tree, MCTSOptions::default(), stop));
+            session.set_priority(priority);
+            session
+        };
+        let (low, high) = (session(1), session(3));
+        let mut scheduler = Scheduler::default();
+        scheduler.add(Arc::clone(&low));
+        scheduler.add(Arc::clone(&high));
+        let mut batches = (0, 0);
+        for _ in 0..400 {
+            let next = scheduler.next().unwrap();
+            if Arc::ptr_eq(&next, &low) {
+                batches.0 += 1;
+            } else {
+                batches.1 += 1;
+            }
+        }
+        assert_eq!(batches, (100, 300));
+
+        // A new session isn't owed the batches it missed.
+        let late = session(1);
+        scheduler.add(Arc::clone(&late));
+        let picks = (0..5).filter(|_| Arc::ptr_eq(&scheduler.next().unwrap(), &late));
+        assert!(picks.count() <= 2);
+
+        low.stop.store(true, Relaxed);
+        high.stop.store(true, Relaxed);
+        assert!(Arc::ptr_eq(&scheduler.next().unwrap(), &late));
+        assert_eq!(scheduler.sessions.len(), 1);
+    }
+
+    #[test]
+    fn test_pool() {
+        use crate::gomoku::{Move, State};
+
+        let pool = SearchPool::new(2);
+        let options = MCTSOptions::default().without_opening_book();
+        let mut engines = [1, 3].map(|priority| {
+            let mut engine = MonteCarloTreeSearchContinue::new(options.clone());
+            engine.start_simulating_in(&pool, priority);
+            engine
+        });
+        assert_eq!(pool.num_sessions(), 2);
+        engines[1].change_cur_state(&State::new(vec![Move::new(7, 7)]));
+        for engine in &mut engines {
+            engine.set_timeout(std::time::Duration::from_millis(200));
+            let state = engine.cur_state();
+            assert!(engine.choose_move(&state).is_some());
+            assert!(engine.analysis(1).unwrap().total_visits > 0);
+        }
+        drop(engines);
+        assert_eq!(pool.num_sessions(), 0);
+    }
+}
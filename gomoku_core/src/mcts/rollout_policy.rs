@@ -1,4 +1,4 @@
-use rand::{rngs::ThreadRng, seq::SliceRandom};
+use rand::{seq::SliceRandom, Rng};
 
 use crate::{
     gomoku::{Gomoku, Move, State},
@@ -14,7 +14,7 @@ impl RolloutPolicy {
     /// Custom function to choose random move during rollouts.
     /// Implementations can bias towards certain moves, ensure winning moves, etc.
     /// The provided move vec is for scratch space.
-    fn random_move(state: &mut State, move_scratch: &mut Vec<Move>, rng: &mut ThreadRng) -> Move {
+    fn random_move(state: &mut State, move_scratch: &mut Vec<Move>, rng: &mut impl Rng) -> Move {
         Gomoku::generate_moves(state, move_scratch);
         *move_scratch
             .choose_weighted(rng, |m| {
@@ -25,8 +25,7 @@ impl RolloutPolicy {
     }
 
     /// Implementation of a rollout over many random moves. Not needed to be overridden.
-    pub fn rollout(options: &MCTSOptions, state: &State) -> i32 {
-        let mut rng = rand::thread_rng();
+    pub fn rollout(options: &MCTSOptions, state: &State, rng: &mut impl Rng) -> i32 {
         let mut depth = options.max_rollout_depth;
         let mut state = state.clone();
         let mut moves = Vec::new();
@@ -58,7 +57,7 @@ impl RolloutPolicy {
             }
 
             moves.clear();
-            let m = Self::random_move(&mut state, &mut moves, &mut rng);
+            let m = Self::random_move(&mut state, &mut moves, rng);
             Gomoku::apply(&mut state, &m);
             sign = -sign;
             depth -= 1;
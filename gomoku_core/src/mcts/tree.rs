@@ -0,0 +1,436 @@
+use std::collections::BinaryHeap;
+use std::sync::atomic::Ordering::*;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+
+use super::arena::Arena;
+use super::node::{expansion_moves, prioritized_moves, Children, Node};
+use super::{LOSS, WIN};
+use crate::gomoku::{Gomoku, Move, Rule, State, Symmetry};
+use crate::interface::Game;
+
+// The search tree, rooted at the empty board.
+pub struct Tree {
+    pub root: Node,
+    // All the other nodes.
+    pub arena: Arena<Node>,
+    // None means unbounded.
+    max_nodes: Option<usize>,
+    // Swapped as a whole, so neither readers nor writers ever wait.
+    search_root: ArcSwap<SearchRoot>,
+}
+
+// The position the workers search from, set by `Tree::set_search_root`.
+pub struct SearchRoot {
+    // Incremented every time the search root is set.
+    pub epoch: u64,
+    // The position as it was set.
+    pub state: State,
+    // Where the position is in the tree, if it is legal.
+    found: Option<(NodeIndex, State, Symmetry)>,
+}
+
+#[derive(Clone, Copy)]
+enum NodeIndex {
+    Root,
+    Arena(usize),
+}
+
+impl SearchRoot {
+    // The node of the search root, which must be from `tree`.
+    pub fn found<'a>(&self, tree: &'a Tree) -> Option<Found<'a>> {
+        let (index, state, symmetry) = self.found.as_ref()?;
+        let node = match *index {
+            NodeIndex::Root => &tree.root,
+            NodeIndex::Arena(index) => &tree.arena.get(index, 1)[0],
+        };
+        Some(Found {
+            node,
+            state: state.clone(),
+            symmetry: *symmetry,
+        })
+    }
+}
+
+// Where a state was found in the tree.
+pub struct Found<'a> {
+    pub node: &'a Node,
+    // The state of the node, which may be a symmetric variant of the state
+    // that was looked up.
+    pub state: State,
+    // Transforms moves of the state that was looked up into moves of the tree.
+    pub symmetry: Symmetry,
+}
+
+impl Tree {
+    pub fn new(max_nodes: Option<usize>) -> Self {
+        Tree {
+            root: Node::new(None),
+            arena: Arena::new(),
+            max_nodes,
+            search_root: ArcSwap::from_pointee(SearchRoot {
+                epoch: 0,
+                state: State::default(),
+                found: Some((NodeIndex::Root, State::default(), Symmetry::Identity)),
+            }),
+        }
+    }
+
+    // Search from `state` from now on. Returns the epoch of the new root,
+    // which is unchanged if the state is.
+    pub fn set_search_root(&self, state: &State, symmetry_ply: usize) -> u64 {
+        let root = self.search_root.load();
+        if root.state.pieces == state.pieces && root.state.rule == state.rule {
+            return root.epoch;
+        }
+        let found = self
+            .find_or_expand(&state.pieces, symmetry_ply)
+            .map(|found| {
+                let index = match self.arena.index_of(found.node) {
+                    Some(index) => NodeIndex::Arena(index),
+                    None => NodeIndex::Root,
+                };
+                // The nodes only know the moves, the rule is the tree's.
+                (index, found.state.with_rule(state.rule), found.symmetry)
+            });
+        let previous = self.search_root.rcu(|root| SearchRoot {
+            epoch: root.epoch + 1,
+            state: state.clone(),
+            found: found.clone(),
+        });
+        previous.epoch + 1
+    }
+
+    pub fn search_root(&self) -> Arc<SearchRoot> {
+        self.search_root.load_full()
+    }
+
+    // The rule of the positions of the tree, the one of its search root, as
+    // the statistics of a rule don't hold for the other.
+    pub fn rule(&self) -> Rule {
+        self.search_root.load().state.rule
+    }
+
+    // Whether `epoch` is the epoch of the current search root.
+    pub fn is_current(&self, epoch: u64) -> bool {
+        self.search_root.load().epoch == epoch
+    }
+
+    // Number of nodes allocated, including the root.
+    pub fn num_nodes(&self) -> usize {
+        self.arena.len() + 1
+    }
+
+    // Whether the node budget is spent, so expanding a node may fail.
+    pub fn is_full(&self) -> bool {
+        self.max_nodes
+            .is_some_and(|max_nodes| self.num_nodes() + 225 > max_nodes)
+    }
+
+    // A new tree searching `state`, with the statistics of this one for it and
+    // the most visited nodes below it, up to a quarter of the node budget.
+    // The nodes of the other positions are dropped along with this tree.
+    pub fn pruned(&self, state: &State, symmetry_ply: usize) -> Tree {
+        let tree = Tree::new(self.max_nodes);
+        tree.set_search_root(state, symmetry_ply);
+        let (Some(from), Some(to)) = (self.find(&state.pieces), tree.search_root().found(&tree))
+        else {
+            return tree;
+        };
+        // The same position may be stored in another orientation.
+        if from.state.pieces != to.state.pieces {
+            return tree;
+        }
+        let max_nodes = self.max_nodes.map_or(usize::MAX, |max_nodes| max_nodes / 4);
+        let to = to.node;
+        to.copy_stats(from.node);
+        // Copy the children of the most visited nodes first.
+        let mut nodes = vec![(from.node, to)];
+        let mut queue = BinaryHeap::from([(from.node.visits.load(Relaxed), 0)]);
+        while let Some((_, index)) = queue.pop() {
+            let (from, to) = nodes[index];
+            let Some(children) = from.children(&self.arena) else {
+                continue;
+            };
+            if tree.num_nodes() + children.len() > max_nodes {
+                break;
+            }
+            let start = tree.arena.alloc(
+                children
+                    .iter()
+                    .map(Node::copy)
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+            );
+            let copies =
+                to.try_set_children(&tree.arena, start, children.len(), children.num_moves());
+            for (child, copy) in children.iter().zip(copies.iter()) {
+                queue.push((child.visits.load(Relaxed), nodes.len()));
+                nodes.push((child, copy));
+            }
+        }
+        tree
+    }
+
+    // Expand the node with all of its children, unless that would exceed the
+    // node budget and `force` isn't set. Returns the children of the node, if
+    // it has any.
+    pub fn expand(
+        &self,
+        node: &Node,
+        state: &State,
+        collapse_symmetries: bool,
+        force: bool,
+    ) -> Option<Children<'_>> {
+        if let Some(children) = node.children(&self.arena) {
+            return Some(children);
+        }
+        let moves = expansion_moves(state, collapse_symmetries);
+        let start = self.alloc(&moves, force)?;
+        Some(node.try_set_children(&self.arena, start, moves.len(), moves.len()))
+    }
+
+    // Add the next segment of children to the node, most promising moves
+    // first, or its first `initial_children` children if it isn't expanded
+    // yet. Returns the children of the node, if it has any.
+    pub fn widen(
+        &self,
+        node: &Node,
+        state: &State,
+        collapse_symmetries: bool,
+        initial_children: usize,
+        force: bool,
+    ) -> Option<Children<'_>> {
+        let children = node.children(&self.arena);
+        if children.is_some_and(|children| children.is_complete()) {
+            return children;
+        }
+        // The order has to be the same every time the node is widened.
+        let moves = prioritized_moves(state, collapse_symmetries);
+        let Some(children) = children else {
+            let len = initial_children.max(1).min(moves.len());
+            let start = self.alloc(&moves[..len], force)?;
+            return Some(node.try_set_children(&self.arena, start, len, moves.len()));
+        };
+        let segment = &moves[children.len()..children.len() + children.next_segment_len()];
+        match self.alloc(segment, force) {
+            Some(start) => Some(node.try_add_segment(&self.arena, &children, start)),
+            None => Some(children),
+        }
+    }
+
+    // Allocate children for the moves, unless that would exceed the node
+    // budget and `force` isn't set.
+    fn alloc(&self, moves: &[Move], force: bool) -> Option<usize> {
+        if !force
+            && self
+                .max_nodes
+                .is_some_and(|max_nodes| self.num_nodes() + moves.len() > max_nodes)
+        {
+            return None;
+        }
+        Some(self.arena.alloc(moves.iter().map(|m| Node::new(Some(*m)))))
+    }
+
+    // Find the node of the state reached by playing `moves` from the root.
+    // Moves leading to symmetric positions may have been collapsed into a
+    // single child, in which case the path in the tree is a symmetric variant
+    // of `moves`.
+    pub fn find(&self, moves: &[Move]) -> Option<Found<'_>> {
+        self.walk(moves, None)
+    }
+
+    // Like find, but expands the nodes on the path if they aren't already,
+    // even if that exceeds the node budget.
+    pub fn find_or_expand(&self, moves: &[Move], symmetry_ply: usize) -> Option<Found<'_>> {
+        self.walk(moves, Some(symmetry_ply))
+    }
+
+    fn walk<'a>(&'a self, moves: &[Move], expand_symmetry_ply: Option<usize>) -> Option<Found<'a>> {
+        let mut node = &self.root;
+        let mut state = State::default();
+        let mut symmetry = Symmetry::Identity;
+        for m in moves {
+            let mut children = match expand_symmetry_ply {
+                Some(symmetry_ply) => {
+                    self.expand(node, &state, state.pieces.len() < symmetry_ply, true)?
+                }
+                None => node.children(&self.arena)?,
+            };
+            let m = m.transform(symmetry);
+            let child = |children: &Children<'a>| {
+                if let Some(child) = children.iter().find(|n| n.m == Some(m)) {
+                    return Some((Symmetry::Identity, child));
+                }
+                // The move may have been collapsed into a symmetric one.
+                state.symmetries().into_iter().find_map(|g| {
+                    let child = children.iter().find(|n| n.m == Some(m.transform(g)))?;
+                    Some((g, child))
+                })
+            };
+            let (stabilizer, child) = loop {
+                if let Some(found) = child(&children) {
+                    break found;
+                }
+                // The move may not have a child yet, if the node was widened
+                // progressively.
+                let symmetry_ply = expand_symmetry_ply.filter(|_| !children.is_complete())?;
+                let collapse_symmetries = state.pieces.len() < symmetry_ply;
+                children = self.widen(node, &state, collapse_symmetries, 1, true)?;
+            };
+            symmetry = symmetry.then(stabilizer);
+            node = child;
+            Gomoku::apply(&mut state, node.m.as_ref().unwrap());
+        }
+        Some(Found {
+            node,
+            state,
+            symmetry,
+        })
+    }
+
+    // Backpropagate the reward of a simulation run from the node at the end
+    // of `moves` to all of its ancestors.
+    pub fn propagate_reward(&self, reward: i32, moves: &[Move]) {
+        let mut path = vec![&self.root];
+        for m in moves {
+            let children = path.last().unwrap().children(&self.arena).unwrap();
+            match children.iter().find(|n| n.m == Some(*m)) {
+                Some(child) => path.push(child),
+                None => return,
+            }
+        }
+        // Only recompute bounds while they keep changing.
+        let mut bounds_changed = path.pop().unwrap().bounds() != (-1, 1);
+
+        let mut reward = reward;
+        for node in path.into_iter().rev() {
+            // Ancestors alternate between the two players.
+            reward = -reward;
+            node.pre_update_stats();
+            if bounds_changed {
+                bounds_changed = node.update_bounds(&self.arena);
+            }
+            let result = match node.proven_value() {
+                Some(1) => WIN,
+                Some(-1) => LOSS,
+                _ => reward.clamp(-1, 1),
+            };
+            node.update_stats(result);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_find_collapsed() {
+        let tree = Tree::new(None);
+        let children = tree.expand(&tree.root, &State::default(), true, false);
+        assert_eq!(children.unwrap().len(), 36);
+
+        let moves = [Move::new(14, 13), Move::new(7, 7), Move::new(3, 4)];
+        let found = tree.find_or_expand(&moves, 2).unwrap();
+        assert_eq!(found.state.pieces[0], Move::new(0, 1));
+        for (m, tree_move) in moves.iter().zip(found.state.pieces.iter()) {
+            assert_eq!(m.transform(found.symmetry), *tree_move);
+        }
+        assert!(std::ptr::eq(tree.find(&moves).unwrap().node, found.node));
+        assert!(tree.find(&[Move::new(7, 7), Move::new(7, 7)]).is_none());
+    }
+
+    #[test]
+    fn test_node_budget() {
+        let tree = Tree::new(Some(300));
+        let state = State::default();
+        assert!(tree.expand(&tree.root, &state, false, false).is_some());
+        assert_eq!(tree.num_nodes(), 226);
+
+        let child = &tree.root.children(&tree.arena).unwrap()[0];
+        let state = State::new(vec![child.m.unwrap()]);
+        assert!(tree.expand(child, &state, false, false).is_none());
+        assert_eq!(tree.num_nodes(), 226);
+        assert!(tree.expand(child, &state, false, true).is_some());
+        assert_eq!(tree.num_nodes(), 450);
+    }
+
+    #[test]
+    fn test_pruned() {
+        let tree = Tree::new(Some(1800));
+        let children = tree
+            .expand(&tree.root, &State::default(), false, true)
+            .unwrap();
+        for child in children.iter().take(7) {
+            tree.expand(child, &State::new(vec![child.m.unwrap()]), false, true);
+        }
+        assert!(tree.is_full());
+        let second = &children[1];
+        second.visits.store(20, Relaxed);
+        let state = State::new(vec![second.m.unwrap()]);
+
+        let pruned = tree.pruned(&state, 0);
+        assert!(!pruned.is_full());
+        let found = pruned.search_root().found(&pruned).unwrap();
+        assert_eq!(found.state.pieces, state.pieces);
+        assert_eq!(found.node.visits.load(Relaxed), 20);
+        assert_eq!(found.node.children(&pruned.arena).unwrap().len(), 224);
+        // The other first moves are there, but without their children.
+        let children = pruned.root.children(&pruned.arena).unwrap();
+        assert!(children[0].children(&pruned.arena).is_none());
+        assert_eq!(pruned.num_nodes(), 1 + 225 + 224);
+    }
+
+    #[test]
+    fn test_widen() {
+        let tree = Tree::new(None);
+        let state = State::default();
+        let children = tree.widen(&tree.root, &state, false, 3, false).unwrap();
+        assert_eq!(children.len(), 3);
+        assert_eq!(children[0].m, Some(Move::new(7, 7)));
+
+        // Segments double in length.
+        for len in [6, 12, 24] {
+            let children = tree.widen(&tree.root, &state, false, 3, false).unwrap();
+            assert_eq!(children.len(), len);
+        }
+        // Even if all the children lose, a move without a child might not.
+        for child in tree.root.children(&tree.arena).unwrap().iter() {
+            child.prove(-1);
+        }
+        assert!(!tree.root.update_bounds(&tree.arena));
+        assert_eq!(tree.root.bounds(), (-1, 1));
+
+        // Looking up a move widens the node until it has a child for it.
+        let found = tree.find_or_expand(&[Move::new(0, 0)], 0).unwrap();
+        assert_eq!(found.node.m, Some(Move::new(0, 0)));
+        let children = tree.root.children(&tree.arena).unwrap();
+        assert!(children.is_complete());
+        assert_eq!(children.len(), 225);
+        assert_eq!(children.iter().filter(|n| n.m == found.node.m).count(), 1);
+    }
+
+    #[test]
+    fn test_search_root() {
+        let tree = Tree::new(None);
+        let root = tree.search_root();
+        assert!(std::ptr::eq(root.found(&tree).unwrap().node, &tree.root));
+
+        let state = State::new(vec![Move::new(7, 7), Move::new(6, 8)]);
+        let epoch = tree.set_search_root(&state, 0);
+        assert!(!tree.is_current(root.epoch));
+        assert_eq!(tree.set_search_root(&state, 0), epoch);
+        assert!(tree.is_current(epoch));
+        let found = tree.search_root().found(&tree).unwrap();
+        assert_eq!(found.state.pieces, state.pieces);
+        assert!(std::ptr::eq(
+            found.node,
+            tree.find(&state.pieces).unwrap().node
+        ));
+
+        tree.set_search_root(&State::new(vec![Move::new(7, 7), Move::new(7, 7)]), 0);
+        assert!(tree.search_root().found(&tree).is_none());
+    }
+}
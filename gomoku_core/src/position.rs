@@ -0,0 +1,258 @@
+//! Setting up positions from board diagrams and compact position strings.
+//!
+//! A diagram is the layout printed by `State`'s `Display`: 15 rows of 15
+//! points, `X` for black, `O` for white and `_` for empty points. Row numbers
+//! and the column header are optional, and so are the spaces between points.
+//!
+//! A compact position string lists the rows from top to bottom separated by
+//! `/`, with `x` for black, `o` for white, and runs of empty points as
+//! numbers, e.g. `15/15/15/15/15/15/15/7x7/8o6/15/15/15/15/15/15`.
+//!
+//! As only the stones are known, the moves of the resulting `State` are in an
+//! arbitrary order, alternating between black and white. Black moves first,
+//! so black has as many stones as white when black is to move, and one more
+//! when white is to move.
+
+use std::fmt::Display;
+
+use crate::gomoku::{Direction, Move, State, Stone};
+
+/// The error returned when a position can't be set up.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParsePositionError(String);
+
+impl Display for ParsePositionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid position: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParsePositionError {}
+
+fn error(message: impl Into<String>) -> ParsePositionError {
+    ParsePositionError(message.into())
+}
+
+type Board = [[Option<Stone>; 15]; 15];
+
+impl State {
+    /// Set up the position of a board diagram, as printed by `Display`.
+    pub fn from_diagram(diagram: &str) -> Result<State, ParsePositionError> {
+        let mut rows = Vec::new();
+        for line in diagram.lines() {
+            let line = line.trim();
+            // Skip blank lines and the column header.
+            if line.is_empty() || line.replace(' ', "") == "abcdefghijklmno" {
+                continue;
+            }
+            let points = line
+                .trim_start_matches(|c: char| c.is_ascii_digit())
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .map(|c| match c {
+                    'X' | 'x' => Ok(Some(Stone::Black)),
+                    'O' | 'o' => Ok(Some(Stone::White)),
+                    '_' | '.' => Ok(None),
+                    c => Err(error(format!("unexpected '{c}' in row {}", rows.len()))),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if points.len() != 15 {
+                return Err(error(format!("row {} doesn't have 15 points", rows.len())));
+            }
+            rows.push(points);
+        }
+        if rows.len() != 15 {
+            return Err(error(format!("found {} rows instead of 15", rows.len())));
+        }
+        let mut board = [[None; 15]; 15];
+        for (row, points) in rows.into_iter().enumerate() {
+            board[row].copy_from_slice(&points);
+        }
+        Self::from_board(&board)
+    }
+
+    /// Set up the position of a compact position string.
+    pub fn from_compact(position: &str) -> Result<State, ParsePositionError> {
+        let rows = position.trim().split('/').collect::<Vec<_>>();
+        if rows.len() != 15 {
+            return Err(error(format!("found {} rows instead of 15", rows.len())));
+        }
+        let mut board = [[None; 15]; 15];
+        for (row, line) in rows.into_iter().enumerate() {
+            let mut col = 0;
+            let mut empty = 0;
+            for c in line.chars().chain(['/']) {
+                if let Some(digit) = c.to_digit(10) {
+                    empty = empty * 10 + digit as usize;
+                    // Stop long runs of digits before they overflow.
+                    if col + empty > 15 {
+                        return Err(error(format!("row {row} has more than 15 points")));
+                    }
+                    continue;
+                }
+                col += empty;
+                empty = 0;
+                let stone = match c {
+                    'x' | 'X' => Stone::Black,
+                    'o' | 'O' => Stone::White,
+                    '/' => break,
+                    c => return Err(error(format!("unexpected '{c}' in row {row}"))),
+                };
+                if col >= 15 {
+                    return Err(error(format!("row {row} has more than 15 points")));
+                }
+                board[row][col] = Some(stone);
+                col += 1;
+            }
+            if col != 15 {
+                return Err(error(format!("row {row} doesn't have 15 points")));
+            }
+        }
+        Self::from_board(&board)
+    }
+
+    /// The compact position string of this position.
+    pub fn to_compact(&self) -> String {
+        let board = self.board();
+        let rows = board.iter().map(|line| {
+            let mut row = String::new();
+            let mut empty = 0;
+            for point in line {
+                let stone = match point {
+                    None => {
+                        empty += 1;
+                        continue;
+                    }
+                    Some(Stone::Black) => 'x',
+                    Some(Stone::White) => 'o',
+                };
+                if empty > 0 {
+                    row += &empty.to_string();
+                    empty = 0;
+                }
+                row.push(stone);
+            }
+            if empty > 0 {
+                row += &empty.to_string();
+            }
+            row
+        });
+        rows.collect::<Vec<_>>().join("/")
+    }
+
+    pub(crate) fn board(&self) -> Board {
+        let mut board = [[None; 15]; 15];
+        for (i, m) in self.pieces.iter().enumerate() {
+            let (row, col) = m.get_coord();
+            board[row][col] = Some(if i % 2 == 0 {
+                Stone::Black
+            } else {
+                Stone::White
+            });
+        }
+        board
+    }
+
+    fn from_board(board: &Board) -> Result<State, ParsePositionError> {
+        let stones = |stone| {
+            (0..225)
+                .map(|i| Move::new(i / 15, i % 15))
+                .filter(|m| {
+                    let (row, col) = m.get_coord();
+                    board[row][col] == Some(stone)
+                })
+                .collect::<Vec<_>>()
+        };
+        let (mut black, mut white) = (stones(Stone::Black), stones(Stone::White));
+        if black.len() != white.len() && black.len() != white.len() + 1 {
+            return Err(error(format!(
+                "{} black and {} white stones, black should have as many or one more",
+                black.len(),
+                white.len()
+            )));
+        }
+        let (just_moved, to_move) = if black.len() > white.len() {
+            (Stone::Black, Stone::White)
+        } else {
+            (Stone::White, Stone::Black)
+        };
+        if five(board, to_move).is_some() {
+            return Err(error(format!(
+                "{to_move:?} has five in a row, but is to move"
+            )));
+        }
+
+        // Play a stone of a five last, so the game is won.
+        if let Some(m) = five(board, just_moved) {
+            let stones = if just_moved == Stone::Black {
+                &mut black
+            } else {
+                &mut white
+            };
+            stones.retain(|stone| *stone != m);
+            stones.push(m);
+        }
+        let mut pieces = Vec::with_capacity(black.len() + white.len());
+        for (i, m) in black.iter().enumerate() {
+            pieces.push(*m);
+            pieces.extend(white.get(i));
+        }
+        Ok(State::new(pieces))
+    }
+}
+
+// A stone of a five in a row of the given color, if any.
+fn five(board: &Board, stone: Stone) -> Option<Move> {
+    let at = |row: isize, col: isize| {
+        (0..15).contains(&row)
+            && (0..15).contains(&col)
+            && board[row as usize][col as usize] == Some(stone)
+    };
+    for row in 0..15 {
+        for col in 0..15 {
+            for direction in Direction::ALL {
+                let (dr, dc) = direction.step();
+                if (0..5).all(|i| at(row + i * dr, col + i * dc)) {
+                    return Some(Move::new(row as usize, col as usize));
+                }
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gomoku::Gomoku;
+    use crate::interface::{Game, Winner};
+
+    #[test]
+    fn test_diagram() {
+        let state = State::new(vec![Move::new(7, 7), Move::new(6, 8), Move::new(0, 14)]);
+        let parsed = State::from_diagram(&state.to_string()).unwrap();
+        assert!(parsed.same_position(&state));
+        assert_eq!(parsed.player_to_move(), Stone::White);
+
+        let compact = state.to_compact();
+        assert_eq!(compact, "14x/15/15/15/15/15/8o6/7x7/15/15/15/15/15/15/15");
+        assert!(State::from_compact(&compact).unwrap().same_position(&state));
+
+        let diagram = "
+            X X X X X _ _ _ _ _ _ _ _ _ _
+            O O O O _ _ _ _ _ _ _ _ _ _ _
+        "
+        .to_string()
+            + &"_______________\n".repeat(13);
+        let won = State::from_diagram(&diagram).unwrap();
+        assert_eq!(Gomoku::get_winner(&won), Some(Winner::PlayerJustMoved));
+
+        assert!(State::from_compact("xx13/15/15/15/15/15/15/15/15/15/15/15/15/15/15").is_err());
+        assert!(State::from_compact("x14/15/15").is_err());
+        assert!(State::from_compact("x15/15/15/15/15/15/15/15/15/15/15/15/15/15/15").is_err());
+        assert!(State::from_compact("15x/15/15/15/15/15/15/15/15/15/15/15/15/15/15").is_err());
+        let long_run = "9".repeat(40) + &"/15".repeat(14);
+        assert!(State::from_compact(&long_run).is_err());
+        assert!(State::from_diagram("X O").is_err());
+    }
+}
@@ -0,0 +1,353 @@
+//! Tactical puzzles, to check that changes to the search don't make it miss
+//! wins or forced defences.
+//!
+//! A puzzle file lists puzzles, each starting with a `[name]` line, followed
+//! by its goal and the position, as a board diagram or a compact position
+//! string (see `position`). The goal is either
+//!
+//! - `best h7 l7`: one of these moves must be played, or
+//! - `win 3`: the move must start a win by continuous fours (VCF) in at most
+//!   3 moves, counting the move making five.
+//!
+//! Lines starting with `#` are comments.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::gomoku::{Direction, Gomoku, Move, Rule, State, Stone};
+use crate::interface::Strategy;
+use crate::io_util::invalid_data;
+
+/// What a puzzle asks for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Goal {
+    /// Play one of these moves.
+    Moves(Vec<Move>),
+    /// Win by continuous fours in at most this many moves.
+    WinIn(u32),
+}
+
+#[derive(Clone, Debug)]
+pub struct Puzzle {
+    pub name: String,
+    pub state: State,
+    pub goal: Goal,
+}
+
+impl Puzzle {
+    /// Whether playing `m` solves the puzzle.
+    pub fn is_solved_by(&self, m: Move) -> bool {
+        match &self.goal {
+            Goal::Moves(moves) => moves.contains(&m),
+            Goal::WinIn(moves) => {
+                let mut board = Board::new(&self.state);
+                let stone = self.state.player_to_move();
+                // Unless it makes five, the move has to block any four.
+                let fours = board.five_points(stone.opponent());
+                board.is_empty(m)
+                    && (board.makes_five(m, stone) || fours.iter().all(|&four| four == m))
+                    && board.wins_with(m, stone, *moves)
+            }
+        }
+    }
+
+    /// All the moves solving the puzzle.
+    pub fn solutions(&self) -> Vec<Move> {
+        let board = Board::new(&self.state);
+        (0..225)
+            .map(|i| Move::new(i / 15, i % 15))
+            .filter(|&m| board.is_empty(m) && self.is_solved_by(m))
+            .collect()
+    }
+}
+
+/// Parse a puzzle file.
+pub fn parse(text: &str) -> io::Result<Vec<Puzzle>> {
+    // The name, goal and position lines of each puzzle, with the line number
+    // of its name for errors.
+    let mut blocks: Vec<(usize, &str, Option<&str>, Vec<&str>)> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            blocks.push((i + 1, name.trim(), None, Vec::new()));
+            continue;
+        }
+        let Some((_, _, goal, position)) = blocks.last_mut() else {
+            return Err(invalid_data(format!("line {}: expected a [name]", i + 1)));
+        };
+        if line.starts_with("best ") || line.starts_with("win ") {
+            *goal = Some(line);
+        } else {
+            position.push(line);
+        }
+    }
+
+    let mut puzzles = Vec::with_capacity(blocks.len());
+    for (line, name, goal, position) in blocks {
+        let error = |message: String| invalid_data(format!("puzzle at line {line}: {message}"));
+        let goal = match goal.ok_or_else(|| error("missing goal".to_string()))? {
+            goal if goal.starts_with("best ") => {
+                let moves = goal[5..]
+                    .split_whitespace()
+                    .map(|m| m.parse::<Move>().map_err(|e| error(e.to_string())))
+                    .collect::<io::Result<Vec<_>>>()?;
+                Goal::Moves(moves)
+            }
+            goal => match goal[4..].trim().parse() {
+                Ok(moves) if moves > 0 => Goal::WinIn(moves),
+                _ => return Err(error(format!("invalid goal '{goal}'"))),
+            },
+        };
+        let state = match position[..] {
+            [compact] if compact.contains('/') => State::from_compact(compact),
+            _ => State::from_diagram(&position.join("\n")),
+        }
+        .map_err(|e| error(e.to_string()))?;
+        puzzles.push(Puzzle {
+            name: name.to_string(),
+            state,
+            goal,
+        });
+    }
+    Ok(puzzles)
+}
+
+/// Read a puzzle file.
+pub fn load(path: impl AsRef<Path>) -> io::Result<Vec<Puzzle>> {
+    parse(&fs::read_to_string(path)?)
+}
+
+/// The answer of a strategy to a puzzle.
+#[derive(Clone, Debug)]
+pub struct PuzzleResult {
+    pub m: Option<Move>,
+    pub solved: bool,
+    /// How long the strategy took to choose its move.
+    pub time: Duration,
+}
+
+/// Results of a puzzle suite, in the order of the puzzles.
+#[derive(Clone, Debug, Default)]
+pub struct SuiteResult {
+    pub results: Vec<PuzzleResult>,
+}
+
+impl SuiteResult {
+    pub fn solved(&self) -> usize {
+        self.results.iter().filter(|result| result.solved).count()
+    }
+
+    /// The fraction of puzzles solved.
+    pub fn solve_rate(&self) -> f64 {
+        self.solved() as f64 / self.results.len().max(1) as f64
+    }
+
+    /// The average time taken on the solved puzzles.
+    pub fn mean_time_to_solve(&self) -> Option<Duration> {
+        let solved = self.solved() as u32;
+        let total = self.results.iter().filter(|r| r.solved).map(|r| r.time);
+        (solved > 0).then(|| total.sum::<Duration>() / solved)
+    }
+}
+
+/// Ask a new strategy from `new_strategy` for a move in each puzzle.
+/// `on_result` is called after each puzzle.
+pub fn run_puzzles<S: Strategy<Gomoku>>(
+    puzzles: &[Puzzle],
+    mut new_strategy: impl FnMut() -> S,
+    mut on_result: impl FnMut(&Puzzle, &PuzzleResult),
+) -> SuiteResult {
+    let mut suite = SuiteResult::default();
+    for puzzle in puzzles {
+        let mut strategy = new_strategy();
+        let start = Instant::now();
+        let m = strategy.choose_move(&puzzle.state);
+        let result = PuzzleResult {
+            m,
+            solved: m.is_some_and(|m| puzzle.is_solved_by(m)),
+            time: start.elapsed(),
+        };
+        on_result(puzzle, &result);
+        suite.results.push(result);
+    }
+    suite
+}
+
+// A board to search for wins by continuous fours.
+struct Board {
+    stones: [[Option<Stone>; 15]; 15],
+    rule: Rule,
+}
+
+impl Board {
+    fn new(state: &State) -> Self {
+        let mut board = [[None; 15]; 15];
+        for (i, m) in state.pieces.iter().enumerate() {
+            let (row, col) = m.get_coord();
+            board[row][col] = Some(if i % 2 == 0 {
+                Stone::Black
+            } else {
+                Stone::White
+            });
+        }
+        Board {
+            stones: board,
+            rule: state.rule,
+        }
+    }
+
+    fn is_empty(&self, m: Move) -> bool {
+        let (row, col) = m.get_coord();
+        self.stones[row][col].is_none()
+    }
+
+    fn set(&mut self, m: Move, stone: Option<Stone>) {
+        let (row, col) = m.get_coord();
+        self.stones[row][col] = stone;
+    }
+
+    // Whether playing `stone` at the empty point `m` makes a winning row.
+    fn makes_five(&self, m: Move, stone: Stone) -> bool {
+        let (row, col) = m.get_coord();
+        let run = |dr: isize, dc: isize| {
+            (1..15)
+                .take_while(|i| {
+                    let (r, c) = (row as isize + i * dr, col as isize + i * dc);
+                    (0..15).contains(&r)
+                        && (0..15).contains(&c)
+                        && self.stones[r as usize][c as usize] == Some(stone)
+                })
+                .count()
+        };
+        Direction::ALL.into_iter().any(|direction| {
+            let (dr, dc) = direction.step();
+            let count = 1 + run(dr, dc) + run(-dr, -dc);
+            match self.rule {
+                Rule::Freestyle => count >= 5,
+                Rule::Standard => count == 5,
+            }
+        })
+    }
+
+    // The points where `stone` would make five.
+    fn five_points(&self, stone: Stone) -> Vec<Move> {
+        (0..225)
+            .map(|i| Move::new(i / 15, i % 15))
+            .filter(|&m| self.is_empty(m) && self.makes_five(m, stone))
+            .collect()
+    }
+
+    // Whether `stone`, to move, wins by continuous fours in at most `moves`
+    // moves.
+    fn wins(&mut self, stone: Stone, moves: u32) -> bool {
+        if !self.five_points(stone).is_empty() {
+            return true;
+        }
+        let theirs = self.five_points(stone.opponent());
+        let candidates = match theirs.len() {
+            0 => (0..225)
+                .map(|i| Move::new(i / 15, i % 15))
+                .filter(|&m| self.is_empty(m))
+                .collect(),
+            // The four must be blocked.
+            1 => theirs,
+            _ => return false,
+        };
+        moves > 1
+            && candidates
+                .into_iter()
+                .any(|m| self.wins_with(m, stone, moves))
+    }
+
+    // Whether `stone` wins by continuous fours in at most `moves` moves,
+    // starting with `m`. The opponent must not have a four `m` doesn't block,
+    // or its block of the four would make five.
+    fn wins_with(&mut self, m: Move, stone: Stone, moves: u32) -> bool {
+        if self.makes_five(m, stone) {
+            return true;
+        }
+        if moves == 1 {
+            return false;
+        }
+        self.set(m, Some(stone));
+        let won = match self.five_points(stone)[..] {
+            // Not a four.
+            [] => false,
+            // The block wins for the opponent.
+            [block] if self.makes_five(block, stone.opponent()) => false,
+            [block] => {
+                self.set(block, Some(stone.opponent()));
+                let won = self.wins(stone, moves - 1);
+                self.set(block, None);
+                won
+            }
+            // An open four, or two fours.
+            _ => true,
+        };
+        self.set(m, None);
+        won
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_puzzles() {
+        let puzzles = parse(include_str!("../puzzles/tactics.txt")).unwrap();
+        assert!(puzzles.len() >= 10);
+        for puzzle in &puzzles {
+            let solutions = puzzle.solutions();
+            assert!(!solutions.is_empty(), "{} has no solution", puzzle.name);
+            if let Goal::WinIn(moves @ 2..) = puzzle.goal {
+                // The puzzle can't be solved faster.
+                let faster = Puzzle {
+                    goal: Goal::WinIn(moves - 1),
+                    ..puzzle.clone()
+                };
+                assert!(faster.solutions().is_empty(), "{}", puzzle.name);
+            }
+        }
+
+        let text = "[five]\nwin 1\n15/15/15/15/15/15/15/4xxxx7/4oooo7/15/15/15/15/15/15";
+        let puzzle = &parse(text).unwrap()[0];
+        assert_eq!(puzzle.solutions(), vec![Move::new(7, 3), Move::new(7, 8)]);
+
+        // Under the standard rule, the overline doesn't win.
+        let text = "[overline]\nwin 1\n15/15/15/15/15/15/15/3xxxx1x6/3oooo1o6/15/15/15/15/15/15";
+        let mut puzzle = parse(text).unwrap().remove(0);
+        assert_eq!(puzzle.solutions(), vec![Move::new(7, 2), Move::new(7, 7)]);
+        puzzle.state = puzzle.state.with_rule(Rule::Standard);
+        assert_eq!(puzzle.solutions(), vec![Move::new(7, 2)]);
+
+        assert!(parse("[five]\nwin 0\n15/15").is_err());
+        assert!(parse("win 1").is_err());
+    }
+
+    #[test]
+    fn test_block_making_five() {
+        let mut board = Board::new(&State::default());
+        let stones = [
+            (Stone::Black, [(7, 3), (7, 4), (7, 5), (6, 7), (12, 7)]),
+            (Stone::White, [(7, 2), (8, 7), (9, 7), (10, 7), (11, 7)]),
+        ];
+        for (stone, points) in stones {
+            for (row, col) in points {
+                board.set(Move::new(row, col), Some(stone));
+            }
+        }
+        for col in 5..8 {
+            board.set(Move::new(2, col), Some(Stone::Black));
+        }
+        // Black's open four on row 2 would follow the four on row 7, but
+        // white's block at h7 already makes five.
+        assert!(!board.wins_with(Move::new(7, 6), Stone::Black, 3));
+        assert!(board.wins_with(Move::new(2, 8), Stone::Black, 2));
+    }
+}
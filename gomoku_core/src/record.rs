@@ -0,0 +1,394 @@
+//! Game records: reading and writing the moves of a game.
+//!
+//! Three formats are supported:
+//!
+//! - `Text`, the moves separated by whitespace, as displayed by `Move`,
+//!   e.g. `h7 i8 g6`, after a `rule standard` line for the standard rule.
+//! - `Psq`, the format of the Piskvork gomoku manager: a header line, then
+//!   one `x,y,time` line per move with 1-based coordinates. The rule isn't
+//!   stored, games are read as freestyle.
+//! - `Sgf`, Smart Game Format with `GM[4]` (gomoku), and `RU[Standard]` for
+//!   the standard rule. Only the main line of the game is read.
+//!
+//! Black always moves first. Records are checked to only contain moves on
+//! empty points of a 15x15 board.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::gomoku::{Move, ParseMoveError, Rule, State, Stone};
+use crate::io_util::invalid_data;
+
+/// A game record format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Psq,
+    Sgf,
+}
+
+impl Format {
+    /// The format of a file, from its extension. Unknown extensions are
+    /// assumed to be `Text`.
+    pub fn from_path(path: impl AsRef<Path>) -> Format {
+        let extension = path.as_ref().extension().and_then(|e| e.to_str());
+        match extension.map(|e| e.to_ascii_lowercase()).as_deref() {
+            Some("psq") => Format::Psq,
+            Some("sgf") => Format::Sgf,
+            _ => Format::Text,
+        }
+    }
+
+    pub fn parse(self, record: &str) -> io::Result<State> {
+        let (moves, rule) = match self {
+            Format::Text => parse_text(record)?,
+            Format::Psq => (parse_psq(record)?, Rule::Freestyle),
+            Format::Sgf => parse_sgf(record)?,
+        };
+        let mut state = State::default().with_rule(rule);
+        for m in moves {
+            if state.pieces.contains(&m) {
+                return Err(invalid_data(format!("{m} is played twice")));
+            }
+            state.pieces.push(m);
+        }
+        Ok(state)
+    }
+
+    pub fn write(self, state: &State) -> String {
+        match self {
+            Format::Text => write_text(state),
+            Format::Psq => write_psq(state),
+            Format::Sgf => write_sgf(state, &GameInfo::default()),
+        }
+    }
+}
+
+/// Read a game, in the format given by the extension of the file.
+pub fn load(path: impl AsRef<Path>) -> io::Result<State> {
+    Format::from_path(&path).parse(&fs::read_to_string(&path)?)
+}
+
+/// Write a game, in the format given by the extension of the file.
+pub fn save(path: impl AsRef<Path>, state: &State) -> io::Result<()> {
+    fs::write(&path, Format::from_path(&path).write(state))
+}
+
+fn parse_text(record: &str) -> io::Result<(Vec<Move>, Rule)> {
+    let (rule, record) = match record.trim_start().strip_prefix("rule ") {
+        Some(rest) => {
+            let (rule, moves) = rest.split_once('\n').unwrap_or((rest, ""));
+            (rule.parse().map_err(invalid_data)?, moves)
+        }
+        None => (Rule::Freestyle, record),
+    };
+    let moves = record
+        .split_whitespace()
+        .map(|m| {
+            m.parse()
+                .map_err(|e: ParseMoveError| invalid_data(e.to_string()))
+        })
+        .collect::<io::Result<_>>()?;
+    Ok((moves, rule))
+}
+
+fn write_text(state: &State) -> String {
+    let header = match state.rule {
+        Rule::Freestyle => "",
+        Rule::Standard => "rule standard\n",
+    };
+    let moves = state.pieces.iter().map(|m| m.to_string());
+    header.to_string() + &moves.collect::<Vec<_>>().join(" ") + "\n"
+}
+
+fn parse_psq(record: &str) -> io::Result<Vec<Move>> {
+    let mut lines = record.lines();
+    let header = lines.next().unwrap_or_default();
+    // e.g. "Piskvork 15x15, 11:11, 0"
+    let size = header
+        .strip_prefix("Piskvork ")
+        .and_then(|rest| rest.split(',').next())
+        .ok_or_else(|| invalid_data("not a Piskvork record"))?;
+    if size.trim() != "15x15" {
+        return Err(invalid_data(format!("unsupported board size {size}")));
+    }
+    let mut moves = Vec::new();
+    for line in lines {
+        let fields = line
+            .split(',')
+            .map(|field| field.trim().parse::<usize>())
+            .collect::<Result<Vec<_>, _>>();
+        // The moves are followed by "-1" or the names of the players.
+        let Ok(&[x, y, _time]) = fields.as_deref() else {
+            break;
+        };
+        if !(1..=15).contains(&x) || !(1..=15).contains(&y) {
+            return Err(invalid_data(format!("move {x},{y} is off the board")));
+        }
+        moves.push(Move::new(y - 1, x - 1));
+    }
+    Ok(moves)
+}
+
+fn write_psq(state: &State) -> String {
+    let mut record = String::from("Piskvork 15x15, 11:11, 0\n");
+    for m in &state.pieces {
+        let (row, col) = m.get_coord();
+        record += &format!("{},{},0\n", col + 1, row + 1);
+    }
+    record + "-1\n"
+}
+
+fn parse_sgf(record: &str) -> io::Result<(Vec<Move>, Rule)> {
+    let mut parser = SgfParser {
+        chars: record.trim().chars().peekable(),
+    };
+    let nodes = parser.game_tree()?;
+    let mut moves = Vec::new();
+    let mut rule = Rule::Freestyle;
+    for (i, node) in nodes.iter().enumerate() {
+        for (property, value) in node {
+            match property.as_str() {
+                "GM" if value != "4" => {
+                    return Err(invalid_data(format!("not a gomoku record, GM[{value}]")))
+                }
+                "SZ" if value != "15" && value != "15:15" => {
+                    return Err(invalid_data(format!("unsupported board size {value}")))
+                }
+                "RU" => rule = value.parse().map_err(invalid_data)?,
+                "AB" | "AW" | "AE" => {
+                    return Err(invalid_data("setup properties are not supported"))
+                }
+                "B" | "W" => {
+                    let expected = if moves.len() % 2 == 0 { "B" } else { "W" };
+                    if property != expected {
+                        return Err(invalid_data(format!("{property} moves out of turn")));
+                    }
+                    moves.push(sgf_point(value).ok_or_else(|| {
+                        invalid_data(format!("invalid point {value} in node {i}"))
+                    })?);
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok((moves, rule))
+}
+
+// Two letters, the column then the row, from `a` at the top left.
+fn sgf_point(value: &str) -> Option<Move> {
+    let &[col, row] = value.as_bytes() else {
+        return None;
+    };
+    let coord = |c: u8| (b'a'..b'a' + 15).contains(&c).then(|| (c - b'a') as usize);
+    Some(Move::new(coord(row)?, coord(col)?))
+}
+
+/// Who played a game and how it ended, for SGF records.
+/// Empty fields are left out, except the result of a game won on the board.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GameInfo {
+    pub black: String,
+    pub white: String,
+    /// In SGF notation, e.g. `B+` when black won, `W+R` when black resigned
+    /// or `0` for a draw.
+    pub result: String,
+}
+
+/// An SGF record of the game, including its players and result.
+/// When the last move won the game, the winning line is written as a comment
+/// of that move, and the result defaults to the winner.
+pub fn write_sgf(state: &State, info: &GameInfo) -> String {
+    let line = state.winning_line();
+    let result = match &line {
+        Some(line) if info.result.is_empty() => match line.stone {
+            Stone::Black => "B+".to_string(),
+            Stone::White => "W+".to_string(),
+        },
+        _ => info.result.clone(),
+    };
+    let escape = |value: &str| value.replace('\\', "\\\\").replace(']', "\\]");
+    let mut record = String::from("(;GM[4]FF[4]SZ[15]");
+    if state.rule == Rule::Standard {
+        record += "RU[Standard]";
+    }
+    for (property, value) in [("PB", &info.black), ("PW", &info.white), ("RE", &result)] {
+        if !value.is_empty() {
+            record += &format!("{property}[{}]", escape(value));
+        }
+    }
+    for (i, m) in state.pieces.iter().enumerate() {
+        let (row, col) = m.get_coord();
+        let color = if i % 2 == 0 { 'B' } else { 'W' };
+        let point = |c: usize| (b'a' + c as u8) as char;
+        record += &format!(";{color}[{}{}]", point(col), point(row));
+    }
+    if let Some(line) = line {
+        record += &format!("C[{}]", escape(&line.to_string()));
+    }
+    record + ")\n"
+}
+
+// The properties of a node, in order.
+type SgfNode = Vec<(String, String)>;
+
+struct SgfParser<I: Iterator<Item = char>> {
+    chars: std::iter::Peekable<I>,
+}
+
+impl<I: Iterator<Item = char>> SgfParser<I> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    fn expect(&mut self, expected: char) -> io::Result<()> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(invalid_data(format!("expected '{expected}', found '{c}'"))),
+            None => Err(invalid_data(format!(
+                "expected '{expected}', found the end"
+            ))),
+        }
+    }
+
+    // The nodes of the main line of a game tree, i.e. of the first variation
+    // at each branch.
+    fn game_tree(&mut self) -> io::Result<Vec<SgfNode>> {
+        self.expect('(')?;
+        let mut nodes = Vec::new();
+        loop {
+            self.skip_whitespace();
+            match self.chars.peek() {
+                Some(';') => {
+                    self.chars.next();
+                    nodes.push(self.node()?);
+                }
+                Some('(') => {
+                    nodes.extend(self.game_tree()?);
+                    // Skip the other variations.
+                    self.skip_whitespace();
+                    while self.chars.peek() == Some(&'(') {
+                        self.game_tree()?;
+                        self.skip_whitespace();
+                    }
+                    break;
+                }
+                _ => break,
+            }
+        }
+        self.expect(')')?;
+        Ok(nodes)
+    }
+
+    fn node(&mut self) -> io::Result<SgfNode> {
+        let mut properties = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let mut identifier = String::new();
+            while let Some(c) = self.chars.next_if(|c| c.is_ascii_uppercase()) {
+                identifier.push(c);
+            }
+            if identifier.is_empty() {
+                return Ok(properties);
+            }
+            self.skip_whitespace();
+            if self.chars.peek() != Some(&'[') {
+                return Err(invalid_data(format!("property {identifier} has no value")));
+            }
+            while self.chars.peek() == Some(&'[') {
+                self.chars.next();
+                properties.push((identifier.clone(), self.value()?));
+                self.skip_whitespace();
+            }
+        }
+    }
+
+    fn value(&mut self) -> io::Result<String> {
+        let mut value = String::new();
+        loop {
+            match self.chars.next() {
+                Some(']') => return Ok(value),
+                Some('\\') => value.extend(self.chars.next()),
+                Some(c) => value.push(c),
+                None => return Err(invalid_data("unterminated property value")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn game() -> State {
+        let moves = "h7 i8 g6 o14 a0 h8 i6 g7";
+        State::new(moves.split(' ').map(|m| m.parse().unwrap()).collect())
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for format in [Format::Text, Format::Psq, Format::Sgf] {
+            let record = format.write(&game());
+            let state = format.parse(&record).unwrap();
+            assert_eq!(state.pieces, game().pieces, "{format:?}");
+        }
+        assert_eq!(Format::Text.write(&game()), "h7 i8 g6 o14 a0 h8 i6 g7\n");
+
+        let info = GameInfo {
+            black: "mcts [1s]".to_string(),
+            result: "B+".to_string(),
+            ..GameInfo::default()
+        };
+        let record = write_sgf(&State::new(vec![Move::new(7, 7)]), &info);
+        assert_eq!(record, "(;GM[4]FF[4]SZ[15]PB[mcts [1s\\]]RE[B+];B[hh])\n");
+        assert_eq!(
+            Format::Sgf.parse(&record).unwrap().pieces,
+            vec![Move::new(7, 7)]
+        );
+
+        let won = Format::Text.parse("a0 a1 b0 b1 c0 c1 d0 d1 e0").unwrap();
+        let record = Format::Sgf.write(&won);
+        assert!(record.starts_with("(;GM[4]FF[4]SZ[15]RE[B+];B[aa]"));
+        assert!(record.ends_with(";B[ea]C[Black five from a0 to e0])\n"));
+        assert_eq!(Format::Sgf.parse(&record).unwrap().pieces, won.pieces);
+
+        let standard = game().with_rule(Rule::Standard);
+        for format in [Format::Text, Format::Sgf] {
+            let state = format.parse(&format.write(&standard)).unwrap();
+            assert_eq!(state.pieces, standard.pieces, "{format:?}");
+            assert_eq!(state.rule, Rule::Standard, "{format:?}");
+        }
+        assert!(Format::Text
+            .write(&standard)
+            .starts_with("rule standard\nh7 i8"));
+        assert!(Format::Sgf
+            .write(&standard)
+            .starts_with("(;GM[4]FF[4]SZ[15]RU[Standard];B[hh]"));
+    }
+
+    #[test]
+    fn test_parse() {
+        let psq = "Piskvork 15x15, 11:11, 0\n8,8,1000\n9,9,531\n-1\nengine.exe\n";
+        let state = Format::Psq.parse(psq).unwrap();
+        assert_eq!(state.pieces, vec![Move::new(7, 7), Move::new(8, 8)]);
+
+        let sgf = "(;GM[4]SZ[15]PB[Black]C[a \\] comment]\n;B[hh];W[ii]\n(;B[ab])(;B[cd]))";
+        let state = Format::Sgf.parse(sgf).unwrap();
+        assert_eq!(
+            state.pieces,
+            vec![Move::new(7, 7), Move::new(8, 8), Move::new(1, 0)]
+        );
+
+        assert!(Format::Sgf.parse("(;GM[1];B[hh])").is_err());
+        assert!(Format::Sgf.parse("(;GM[4]RU[Renju];B[hh])").is_err());
+        assert!(Format::Sgf.parse("(;GM[4];W[hh])").is_err());
+        assert!(Format::Psq
+            .parse("Piskvork 20x20, 11:11, 0\n1,1,0\n")
+            .is_err());
+        assert!(Format::Text.parse("h7 h7").is_err());
+        assert!(Format::Text.parse("h7 p3").is_err());
+        assert_eq!(Format::from_path("games/final.SGF"), Format::Sgf);
+    }
+}
@@ -0,0 +1,218 @@
+//! Command line arguments of the game.
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use gomoku_core::{
+    gomoku::{Rule, State},
+    mcts::algorithm::{MCTSOptions, ProgressiveWidening, Resignation, UctFormula},
+};
+
+/// Help on the options, shared by the binaries playing games.
+pub const OPTIONS: &str = "options:
+  --black human|engine    who plays black (default engine)
+  --white human|engine    who plays white (default human)
+  --load FILE             continue a game saved by save (.txt, .psq or .sgf)
+  --rule freestyle|standard
+                          whether overlines win (freestyle, the default) or not,
+                          instead of the rule of the loaded game
+  --time MS               engine time per move (default 5000)
+  --resign                let the engine resign lost games
+  --draw-after N          adjudicate a draw after N moves (default 200)
+  --threads N             number of search threads (default all cores)
+  --exploration C         exploration constant of the search (default 1)
+  --uct ucb1|tuned        selection formula (default ucb1)
+  --rollout-depth N       maximum moves per rollout (default 225)
+  --expand-after N        rollouts of a node before expanding it (default 10)
+  --widening              use progressive widening
+  --ponder                search the predicted reply while the opponent thinks
+  --seed N                seed of the search
+  --verbose               print search statistics after each engine move
+  --help                  show this message";
+
+/// Who plays a color.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Player {
+    Human,
+    Engine,
+}
+
+impl FromStr for Player {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Player::Human),
+            "engine" => Ok(Player::Engine),
+            _ => Err(format!("unknown player \"{s}\", expected human or engine")),
+        }
+    }
+}
+
+/// How many moves to take back to undo the last move of a human, which are
+/// the engine's replies to it as well.
+pub fn takeback_len(state: &State, players: &[Player; 2]) -> usize {
+    let mut moves = 0;
+    while moves < state.pieces.len() {
+        moves += 1;
+        if players[(state.pieces.len() - moves) % 2] == Player::Human {
+            break;
+        }
+    }
+    moves
+}
+
+#[derive(Clone, Debug)]
+pub struct Args {
+    /// The black and white players.
+    pub players: [Player; 2],
+    pub load: Option<String>,
+    rule: Option<Rule>,
+    pub time: Duration,
+    resign: bool,
+    pub draw_after: usize,
+    threads: Option<usize>,
+    exploration: Option<f32>,
+    uct_formula: UctFormula,
+    rollout_depth: u32,
+    expand_after: u32,
+    widening: bool,
+    ponder: bool,
+    seed: Option<u64>,
+    verbose: bool,
+    pub help: bool,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            players: [Player::Engine, Player::Human],
+            load: None,
+            rule: None,
+            time: Duration::from_secs(5),
+            resign: false,
+            draw_after: 200,
+            threads: None,
+            exploration: None,
+            uct_formula: UctFormula::default(),
+            rollout_depth: 225,
+            expand_after: 10,
+            widening: false,
+            ponder: false,
+            seed: None,
+            verbose: false,
+            help: false,
+        }
+    }
+}
+
+/// Parse the value of `option`, the next argument.
+pub fn parse<T: FromStr>(option: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("missing value for {option}"))?;
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {option}: {value}"))
+}
+
+impl Args {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+        let mut parsed = Args::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--black" => parsed.players[0] = parse(&arg, args.next())?,
+                "--white" => parsed.players[1] = parse(&arg, args.next())?,
+                "--load" => parsed.load = Some(parse(&arg, args.next())?),
+                "--rule" => parsed.rule = Some(parse(&arg, args.next())?),
+                "--time" => parsed.time = Duration::from_millis(parse(&arg, args.next())?),
+                "--resign" => parsed.resign = true,
+                "--draw-after" => parsed.draw_after = parse(&arg, args.next())?,
+                "--threads" => parsed.threads = Some(parse(&arg, args.next())?),
+                "--exploration" => parsed.exploration = Some(parse(&arg, args.next())?),
+                "--uct" => parsed.uct_formula = parse(&arg, args.next())?,
+                "--rollout-depth" => parsed.rollout_depth = parse(&arg, args.next())?,
+                "--expand-after" => parsed.expand_after = parse(&arg, args.next())?,
+                "--widening" => parsed.widening = true,
+                "--ponder" => parsed.ponder = true,
+                "--seed" => parsed.seed = Some(parse(&arg, args.next())?),
+                "--verbose" => parsed.verbose = true,
+                "--help" | "-h" => parsed.help = true,
+                _ => return Err(format!("unknown option {arg}")),
+            }
+        }
+        if parsed.threads == Some(0) {
+            return Err("--threads must be at least 1".to_string());
+        }
+        Ok(parsed)
+    }
+
+    /// `game` under the rule given with `--rule`, or else its own, which is
+    /// freestyle for a new game.
+    pub fn apply_rule(&self, game: State) -> State {
+        match self.rule {
+            Some(rule) => game.with_rule(rule),
+            None => game,
+        }
+    }
+
+    /// The search options of the engine.
+    pub fn mcts_options(&self) -> MCTSOptions {
+        let mut options = MCTSOptions::default()
+            .with_max_rollout_depth(self.rollout_depth)
+            .with_rollouts_before_expanding(self.expand_after)
+            .with_uct_formula(self.uct_formula);
+        if let Some(threads) = self.threads {
+            options = options.with_num_threads(threads);
+        }
+        if let Some(exploration) = self.exploration {
+            options = options.with_exploration(exploration);
+        }
+        if self.widening {
+            options = options.with_progressive_widening(ProgressiveWidening::default());
+        }
+        if self.ponder {
+            options = options.ponder();
+        }
+        if self.resign {
+            options = options.with_resignation(Resignation::default());
+        }
+        if let Some(seed) = self.seed {
+            options = options.with_seed(seed);
+        }
+        if self.verbose {
+            options = options.verbose();
+        }
+        options
+    }
+}
+
+#[test]
+fn test_parse() {
+    let args = ["--white", "engine", "--rule", "standard", "--time", "200"];
+    let args = Args::parse(args.into_iter().map(String::from)).unwrap();
+    assert_eq!(args.players, [Player::Engine, Player::Engine]);
+    assert_eq!(args.rule, Some(Rule::Standard));
+    assert_eq!(args.time, Duration::from_millis(200));
+
+    for args in [
+        &["--black"][..],
+        &["--black", "robot"],
+        &["--time", "-1"],
+        &["h7"],
+    ] {
+        assert!(Args::parse(args.iter().map(|s| s.to_string())).is_err());
+    }
+}
+
+#[test]
+fn test_takeback_len() {
+    use gomoku_core::gomoku::Move;
+
+    let state = State::new(vec![Move::new(7, 7), Move::new(7, 8), Move::new(7, 9)]);
+    assert_eq!(takeback_len(&state, &[Player::Engine, Player::Human]), 2);
+    assert_eq!(takeback_len(&state, &[Player::Human, Player::Engine]), 1);
+    assert_eq!(takeback_len(&state, &[Player::Engine, Player::Engine]), 3);
+    assert_eq!(
+        takeback_len(&State::default(), &[Player::Human, Player::Human]),
+        0
+    );
+}
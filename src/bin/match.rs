@@ -0,0 +1,278 @@
+//! Play a match between two engine configurations, e.g.
+//!
+//! ```text
+//! match --games 100 --sprt 0,20 --record games.sgf --time-a 500 --uct-b tuned
+//! ```
+//!
+//! Options ending in `-a` or `-b` configure one of the engines, and the
+//! result is reported from the perspective of engine A.
+
+use std::process::ExitCode;
+use std::time::Duration;
+
+use gomoku::args::parse;
+use gomoku_core::{
+    engine_match::{run_match, MatchOptions, Sprt, SprtResult},
+    mcts::{
+        algorithm::{
+            MCTSOptions, MonteCarloTreeSearchContinue, ProgressiveWidening, Resignation, UctFormula,
+        },
+        pool::SearchPool,
+    },
+};
+
+const USAGE: &str = "usage: match [options]
+  --games N             number of games (default 100)
+  --opening-moves N     random moves played before each pair of games (default 3)
+  --seed N              seed of the openings and engines
+  --sprt ELO0,ELO1      stop once A is shown to be ELO0 or ELO1 stronger than B
+  --record FILE         write the games to an SGF file
+  --draw-after N        adjudicate a draw after N moves (default 200)
+  --time-limit MS       lose on time when a move takes longer
+  --threads N           search threads shared by the engines (default all cores)
+per engine, with the suffix -a or -b:
+  --time-X MS           time per move (default 1000)
+  --playouts-X N        search N playouts per move instead, on a single thread
+  --exploration-X C     exploration constant
+  --uct-X ucb1|tuned    selection formula
+  --widening-X          use progressive widening
+  --resign-X            resign lost games
+  --ponder-X            search the predicted reply while the opponent thinks";
+
+// The configuration of one engine.
+#[derive(Clone)]
+struct Engine {
+    time: Duration,
+    playouts: Option<u32>,
+    exploration: Option<f32>,
+    uct_formula: UctFormula,
+    widening: bool,
+    resign: bool,
+    ponder: bool,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self {
+            time: Duration::from_millis(1000),
+            playouts: None,
+            exploration: None,
+            uct_formula: UctFormula::default(),
+            widening: false,
+            resign: false,
+            ponder: false,
+        }
+    }
+}
+
+impl Engine {
+    fn name(&self) -> String {
+        let mut name = match self.playouts {
+            Some(playouts) => format!("mcts {playouts}p"),
+            None => format!("mcts {}ms", self.time.as_millis()),
+        };
+        if let Some(exploration) = self.exploration {
+            name += &format!(" c={exploration}");
+        }
+        if self.uct_formula == UctFormula::Ucb1Tuned {
+            name += " tuned";
+        }
+        if self.widening {
+            name += " pw";
+        }
+        if self.ponder {
+            name += " ponder";
+        }
+        name
+    }
+
+    fn create(&self, pool: &SearchPool, seed: Option<u64>) -> MonteCarloTreeSearchContinue {
+        let mut options = MCTSOptions::default()
+            .with_max_rollout_depth(225)
+            .with_rollouts_before_expanding(10)
+            .with_uct_formula(self.uct_formula);
+        if let Some(playouts) = self.playouts {
+            options = options.with_playouts(playouts);
+        }
+        if let Some(exploration) = self.exploration {
+            options = options.with_exploration(exploration);
+        }
+        if self.widening {
+            options = options.with_progressive_widening(ProgressiveWidening::default());
+        }
+        if self.resign {
+            options = options.with_resignation(Resignation::default());
+        }
+        if self.ponder {
+            options = options.ponder();
+        }
+        if let Some(seed) = seed {
+            options = options.with_seed(seed);
+        }
+        let mut engine = MonteCarloTreeSearchContinue::new(options);
+        engine.set_timeout(self.time);
+        engine.start_simulating_in(pool, 1);
+        engine
+    }
+}
+
+struct Args {
+    games: u32,
+    opening_moves: usize,
+    seed: Option<u64>,
+    sprt: Option<Sprt>,
+    record: Option<String>,
+    draw_after: usize,
+    time_limit: Option<Duration>,
+    threads: Option<usize>,
+    engines: [Engine; 2],
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args {
+        games: 100,
+        opening_moves: 3,
+        seed: None,
+        sprt: None,
+        record: None,
+        draw_after: 200,
+        time_limit: None,
+        threads: None,
+        engines: [Engine::default(), Engine::default()],
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--games" => parsed.games = parse(&arg, args.next())?,
+            "--opening-moves" => parsed.opening_moves = parse(&arg, args.next())?,
+            "--seed" => parsed.seed = Some(parse(&arg, args.next())?),
+            "--record" => parsed.record = Some(parse(&arg, args.next())?),
+            "--draw-after" => parsed.draw_after = parse(&arg, args.next())?,
+            "--time-limit" => {
+                parsed.time_limit = Some(Duration::from_millis(parse(&arg, args.next())?))
+            }
+            "--threads" => parsed.threads = Some(parse(&arg, args.next())?),
+            "--sprt" => {
+                let value: String = parse(&arg, args.next())?;
+                let (elo0, elo1) = value
+                    .split_once(',')
+                    .and_then(|(elo0, elo1)| Some((elo0.parse().ok()?, elo1.parse().ok()?)))
+                    .ok_or_else(|| format!("invalid value for {arg}: {value}"))?;
+                parsed.sprt = Some(Sprt::new(elo0, elo1));
+            }
+            _ => {
+                let (option, engine) = match arg.rsplit_once('-') {
+                    Some((option, "a")) => (option, &mut parsed.engines[0]),
+                    Some((option, "b")) => (option, &mut parsed.engines[1]),
+                    _ => return Err(format!("unknown option {arg}")),
+                };
+                match option {
+                    "--time" => engine.time = Duration::from_millis(parse(&arg, args.next())?),
+                    "--playouts" => engine.playouts = Some(parse(&arg, args.next())?),
+                    "--exploration" => engine.exploration = Some(parse(&arg, args.next())?),
+                    "--widening" => engine.widening = true,
+                    "--resign" => engine.resign = true,
+                    "--ponder" => engine.ponder = true,
+                    "--uct" => engine.uct_formula = parse(&arg, args.next())?,
+                    _ => return Err(format!("unknown option {arg}")),
+                }
+            }
+        }
+    }
+    Ok(parsed)
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{message}\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let [a, b] = &args.engines;
+    let (name_a, name_b) = (a.name(), b.name());
+    println!("A: {name_a}\nB: {name_b}");
+
+    let mut options = MatchOptions::new(args.games)
+        .with_opening_moves(args.opening_moves)
+        .with_draw_adjudication(args.draw_after)
+        .with_names(name_a, name_b);
+    if let Some(seed) = args.seed {
+        options = options.with_seed(seed);
+    }
+    if let Some(sprt) = args.sprt {
+        options = options.with_sprt(sprt);
+    }
+    if let Some(record) = &args.record {
+        options = options.with_record(record);
+    }
+    if let Some(limit) = args.time_limit {
+        options = options.with_time_limit(limit);
+    }
+
+    // Both engines search on the same threads, so neither slows the other
+    // down by oversubscribing the CPU.
+    let pool = match args.threads {
+        Some(threads) => SearchPool::new(threads),
+        None => SearchPool::default(),
+    };
+    // Each game gets engines seeded differently, or they would all be the same.
+    let seed = |game: u32, engine: u64| {
+        args.seed
+            .map(|seed| seed.wrapping_add(1000 * (2 * game as u64 + engine)))
+    };
+    let result = run_match(
+        |game| a.create(&pool, seed(game, 0)),
+        |game| b.create(&pool, seed(game, 1)),
+        &options,
+        |game, result| {
+            let winner = match game.a_won() {
+                Some(true) => "A wins",
+                Some(false) => "B wins",
+                None => "draw",
+            };
+            println!(
+                "game {}: {winner} ({}) in {} moves, +{} ={} -{}",
+                result.games(),
+                game.result,
+                game.state.pieces.len(),
+                result.wins,
+                result.draws,
+                result.losses
+            );
+        },
+    );
+    let result = match result {
+        Ok(result) => result,
+        Err(err) => {
+            eprintln!("failed to write the record: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    println!(
+        "\nA vs B: +{} ={} -{} ({:.1}%)",
+        result.wins,
+        result.draws,
+        result.losses,
+        result.score() * 100.0
+    );
+    match result.elo() {
+        Some(elo) => println!("Elo difference: {:.1} +/- {:.1}", elo.diff, elo.margin),
+        None => println!("Elo difference: unbounded"),
+    }
+    if let Some(sprt) = &args.sprt {
+        let verdict = match result.sprt {
+            Some(SprtResult::AcceptElo0) => "H0 accepted",
+            Some(SprtResult::AcceptElo1) => "H1 accepted",
+            None => "inconclusive",
+        };
+        println!(
+            "SPRT [{}, {}]: LLR {:.2}, {verdict}",
+            sprt.elo0,
+            sprt.elo1,
+            result.llr(sprt)
+        );
+    }
+    ExitCode::SUCCESS
+}
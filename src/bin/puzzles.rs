@@ -0,0 +1,122 @@
+//! Run a tactical puzzle suite against the search, e.g.
+//!
+//! ```text
+//! puzzles gomoku_core/puzzles/tactics.txt --playouts 20000
+//! ```
+//!
+//! By default, each puzzle is searched for 10000 playouts on a single thread
+//! with a fixed seed, so runs are reproducible.
+
+use std::process::ExitCode;
+use std::time::Duration;
+
+use gomoku::args::parse;
+use gomoku_core::{
+    mcts::algorithm::{MCTSOptions, MonteCarloTreeSearchContinue, ProgressiveWidening},
+    puzzle::{self, Goal},
+};
+
+const USAGE: &str = "usage: puzzles [FILE] [options]
+  FILE              puzzle file (default gomoku_core/puzzles/tactics.txt)
+  --playouts N      playouts per puzzle (default 10000)
+  --time MS         search for this long on all threads instead
+  --threads N       number of search threads, with --time
+  --seed N          seed of the search (default 1)
+  --widening        use progressive widening";
+
+struct Args {
+    path: String,
+    playouts: u32,
+    time: Option<Duration>,
+    threads: Option<usize>,
+    seed: u64,
+    widening: bool,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args {
+        path: "gomoku_core/puzzles/tactics.txt".to_string(),
+        playouts: 10000,
+        time: None,
+        threads: None,
+        seed: 1,
+        widening: false,
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--playouts" => parsed.playouts = parse(&arg, args.next())?,
+            "--time" => parsed.time = Some(Duration::from_millis(parse(&arg, args.next())?)),
+            "--threads" => parsed.threads = Some(parse(&arg, args.next())?),
+            "--seed" => parsed.seed = parse(&arg, args.next())?,
+            "--widening" => parsed.widening = true,
+            _ if arg.starts_with('-') => return Err(format!("unknown option {arg}")),
+            _ => parsed.path = arg,
+        }
+    }
+    Ok(parsed)
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{message}\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let puzzles = match puzzle::load(&args.path) {
+        Ok(puzzles) => puzzles,
+        Err(err) => {
+            eprintln!("failed to read {}: {err}", args.path);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let new_engine = || {
+        let mut options = MCTSOptions::default()
+            .with_max_rollout_depth(225)
+            .with_rollouts_before_expanding(10)
+            .with_seed(args.seed);
+        if args.time.is_none() {
+            options = options.with_playouts(args.playouts);
+        }
+        if let Some(threads) = args.threads {
+            options = options.with_num_threads(threads);
+        }
+        if args.widening {
+            options = options.with_progressive_widening(ProgressiveWidening::default());
+        }
+        let mut engine = MonteCarloTreeSearchContinue::new(options);
+        if let Some(time) = args.time {
+            engine.set_timeout(time);
+        }
+        engine.start_simulating();
+        engine
+    };
+    let result = puzzle::run_puzzles(&puzzles, new_engine, |puzzle, result| {
+        let goal = match &puzzle.goal {
+            Goal::Moves(moves) => {
+                let moves = moves.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+                format!("best {}", moves.join(" "))
+            }
+            Goal::WinIn(moves) => format!("win {moves}"),
+        };
+        let played = result.m.map_or("none".to_string(), |m| m.to_string());
+        let verdict = if result.solved { "ok" } else { "FAILED" };
+        println!(
+            "{verdict:6} {:30} {goal:12} played {played:4} in {:.2?}",
+            puzzle.name, result.time
+        );
+    });
+
+    println!(
+        "\nsolved {}/{} ({:.0}%)",
+        result.solved(),
+        result.results.len(),
+        result.solve_rate() * 100.0
+    );
+    if let Some(time) = result.mean_time_to_solve() {
+        println!("mean time to solve: {time:.2?}");
+    }
+    ExitCode::SUCCESS
+}
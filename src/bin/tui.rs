@@ -0,0 +1,484 @@
+//! A full-screen terminal interface to play against the engine.
+//!
+//! Takes the same options as the `gomoku` binary. The engine thinks on its
+//! own thread, so the board and the live search statistics stay responsive.
+
+use std::io::{self, Write};
+use std::process::ExitCode;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::{
+        Attribute, Color, Print, ResetColor, SetAttribute, SetBackgroundColor, SetForegroundColor,
+    },
+    terminal::{self, ClearType},
+};
+use gomoku::args::{takeback_len, Args, Player, OPTIONS};
+use gomoku_core::{
+    game_result::{GameResult, Termination},
+    gomoku::{Gomoku, Move, State, Stone},
+    interface::Game,
+    mcts::{
+        algorithm::MonteCarloTreeSearchContinue,
+        analysis::{Analysis, AnalysisOptions, Proof},
+    },
+    record,
+};
+
+const KEYS: &str = "arrows move  enter play  u undo  pgup/pgdn history  s save  n new  q quit";
+
+// Restores the terminal when dropped, even on panics.
+struct RawTerminal;
+
+impl RawTerminal {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(RawTerminal)
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+enum Request {
+    // The game moved to this position, search it.
+    Position(State),
+    // Choose a move in this position.
+    Think(State),
+    // Take back this many moves of the game, which may not be the engine's
+    // position yet if it was thinking.
+    TakeBack(State, usize),
+}
+
+// The engine, running on its own thread, and its live search statistics.
+struct EngineHandle {
+    requests: Sender<Request>,
+    // Replies carry the position they were asked for, so moves for a position
+    // that was since undone can be ignored.
+    // Also tells whether the engine resigns instead.
+    replies: Receiver<(State, Option<Move>, bool)>,
+    analysis: Receiver<Analysis>,
+}
+
+impl EngineHandle {
+    fn spawn(mut engine: MonteCarloTreeSearchContinue) -> Self {
+        let analysis = engine.subscribe_analysis(
+            AnalysisOptions::default()
+                .with_interval(Duration::from_millis(250))
+                .with_pv_length(6),
+        );
+        let (requests, receiver) = mpsc::channel();
+        let (sender, replies) = mpsc::channel();
+        thread::spawn(move || {
+            for request in receiver {
+                match request {
+                    Request::Position(state) => engine.change_cur_state(&state),
+                    Request::TakeBack(game, moves) => {
+                        engine.change_cur_state(&game);
+                        engine.take_back(moves);
+                    }
+                    Request::Think(state) => {
+                        let m = engine.choose_move(&state);
+                        if sender.send((state, m, engine.resigns())).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+        EngineHandle {
+            requests,
+            replies,
+            analysis,
+        }
+    }
+
+    fn set_position(&self, state: &State) {
+        let _ = self.requests.send(Request::Position(state.clone()));
+    }
+
+    fn think(&self, state: &State) {
+        let _ = self.requests.send(Request::Think(state.clone()));
+    }
+
+    fn take_back(&self, game: &State, moves: usize) {
+        let _ = self.requests.send(Request::TakeBack(game.clone(), moves));
+    }
+}
+
+struct App {
+    args: Args,
+    // Who plays each color in this game, the players of `args` unless the
+    // human took over for the engine.
+    players: [Player; 2],
+    game: State,
+    // Number of moves shown, less than the game's when browsing the history.
+    view: usize,
+    cursor: (usize, usize),
+    // The position the engine was asked to play in, if any.
+    thinking: Option<State>,
+    analysis: Option<Analysis>,
+    // Set when the game ended off the board, by a resignation.
+    ended: Option<GameResult>,
+    message: String,
+}
+
+impl App {
+    fn viewed(&self) -> State {
+        State::new(self.game.pieces[..self.view].to_vec()).with_rule(self.game.rule)
+    }
+
+    fn is_live(&self) -> bool {
+        self.view == self.game.pieces.len()
+    }
+
+    fn player(&self, stone: Stone) -> Player {
+        self.players[(stone == Stone::White) as usize]
+    }
+
+    fn result(&self) -> Option<GameResult> {
+        self.ended
+            .or_else(|| GameResult::adjudicate(&self.game, self.args.draw_after))
+    }
+
+    fn is_over(&self) -> bool {
+        self.result().is_some()
+    }
+
+    fn play(&mut self, m: Move) {
+        Gomoku::apply(&mut self.game, &m);
+        self.view = self.game.pieces.len();
+        self.cursor = m.get_coord();
+        self.message.clear();
+    }
+
+    fn set_game(&mut self, game: State) {
+        self.game = game;
+        self.view = self.game.pieces.len();
+        self.thinking = None;
+        self.analysis = None;
+        self.ended = None;
+    }
+
+    // Returns false to quit.
+    fn handle_key(&mut self, key: KeyEvent, engine: &EngineHandle) -> bool {
+        let (row, col) = &mut self.cursor;
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Up | KeyCode::Char('k') => *row = row.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => *row = (*row + 1).min(14),
+            KeyCode::Left | KeyCode::Char('h') => *col = col.saturating_sub(1),
+            KeyCode::Right | KeyCode::Char('l') => *col = (*col + 1).min(14),
+            KeyCode::PageUp | KeyCode::Char('[') => self.view = self.view.saturating_sub(1),
+            KeyCode::PageDown | KeyCode::Char(']') => {
+                self.view = (self.view + 1).min(self.game.pieces.len())
+            }
+            KeyCode::Home => self.view = 0,
+            KeyCode::End => self.view = self.game.pieces.len(),
+            KeyCode::Enter | KeyCode::Char(' ') => {
+                let m = Move::new(*row, *col);
+                let to_move = self.game.player_to_move();
+                if !self.is_live() {
+                    self.message = "browsing the history, press End to return".to_string();
+                } else if self.is_over() {
+                    self.message = "the game is over, press n for a new one".to_string();
+                } else if self.player(to_move) == Player::Engine {
+                    self.message = "the engine is to move".to_string();
+                } else if self.game.pieces.contains(&m) {
+                    self.message = format!("{m} is already taken");
+                } else {
+                    self.play(m);
+                    engine.set_position(&self.game);
+                }
+            }
+            KeyCode::Char('u') => {
+                // Take back the engine's replies too, so it is our turn again.
+                let moves = takeback_len(&self.game, &self.players);
+                if moves == 0 {
+                    self.message = "nothing to undo".to_string();
+                    return true;
+                }
+                engine.take_back(&self.game, moves);
+                self.view = self.game.pieces.len() - moves;
+                self.set_game(self.viewed());
+            }
+            KeyCode::Char('n') => {
+                self.set_game(State::default().with_rule(self.game.rule));
+                self.players = self.args.players;
+                engine.set_position(&self.game);
+                self.message = "new game".to_string();
+            }
+            KeyCode::Char('s') => {
+                self.message = match record::save("game.sgf", &self.game) {
+                    Ok(()) => "saved to game.sgf".to_string(),
+                    Err(err) => format!("failed to save game.sgf: {err}"),
+                };
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn status(&self) -> String {
+        let state = self.viewed();
+        let history = format!("move {}/{}", self.view, self.game.pieces.len());
+        let status = match self.result().filter(|_| self.is_live()) {
+            Some(result) => match state.winning_line() {
+                Some(line) => format!("{line}"),
+                None => format!("{result}"),
+            },
+            None if !self.is_live() => "browsing the history".to_string(),
+            None if self.thinking.is_some() => {
+                format!("{:?} (engine) is thinking", state.player_to_move())
+            }
+            None => format!("{:?} to move", state.player_to_move()),
+        };
+        format!("{status}, {history}")
+    }
+
+    fn draw(&self, out: &mut impl Write) -> io::Result<()> {
+        let state = self.viewed();
+        let last = state.pieces.last().copied();
+        let line = state.winning_line().map_or(Vec::new(), |line| line.moves);
+        let mut board = [[None; 15]; 15];
+        for (i, m) in state.pieces.iter().enumerate() {
+            let (row, col) = m.get_coord();
+            board[row][col] = Some(if i % 2 == 0 {
+                Stone::Black
+            } else {
+                Stone::White
+            });
+        }
+
+        queue!(
+            out,
+            cursor::MoveTo(0, 0),
+            Print("    a b c d e f g h i j k l m n o")
+        )?;
+        for (row, points) in board.iter().enumerate() {
+            queue!(
+                out,
+                cursor::MoveTo(0, row as u16 + 1),
+                Print(format!("{row:2}  "))
+            )?;
+            for (col, point) in points.iter().enumerate() {
+                let m = Move::new(row, col);
+                let (symbol, color) = match point {
+                    Some(Stone::Black) => ('X', Color::Yellow),
+                    Some(Stone::White) => ('O', Color::Cyan),
+                    None => ('.', Color::DarkGrey),
+                };
+                if line.contains(&m) {
+                    queue!(out, SetBackgroundColor(Color::DarkGreen))?;
+                } else if last == Some(m) {
+                    queue!(out, SetBackgroundColor(Color::DarkBlue))?;
+                }
+                if self.cursor == (row, col) {
+                    queue!(out, SetAttribute(Attribute::Reverse))?;
+                }
+                queue!(
+                    out,
+                    SetForegroundColor(color),
+                    Print(symbol),
+                    SetAttribute(Attribute::Reset),
+                    ResetColor,
+                    Print(' ')
+                )?;
+            }
+        }
+        let status = self.status();
+        queue!(
+            out,
+            cursor::MoveTo(0, 17),
+            terminal::Clear(ClearType::UntilNewLine),
+            Print(&status),
+            cursor::MoveTo(0, 18),
+            terminal::Clear(ClearType::UntilNewLine),
+            Print(&self.message),
+            cursor::MoveTo(0, 19),
+            SetForegroundColor(Color::DarkGrey),
+            Print(KEYS),
+            ResetColor
+        )?;
+
+        for (i, line) in self.panel(&state).iter().enumerate() {
+            queue!(
+                out,
+                cursor::MoveTo(36, i as u16),
+                terminal::Clear(ClearType::UntilNewLine),
+                Print(line)
+            )?;
+        }
+        out.flush()
+    }
+
+    // The side panel: search statistics of the viewed position, and the
+    // last moves of the game.
+    fn panel(&self, state: &State) -> Vec<String> {
+        let mut lines = vec![format!(
+            "cursor {}",
+            Move::new(self.cursor.0, self.cursor.1)
+        )];
+        lines.push(String::new());
+        match &self.analysis {
+            Some(analysis) if analysis.state.pieces == state.pieces => {
+                lines.push(format!(
+                    "search: {} visits, {} total visits",
+                    analysis.visits, analysis.total_visits
+                ));
+                for info in &analysis.moves {
+                    let result = match info.proven {
+                        Some(Proof::Win) => "win".to_string(),
+                        Some(Proof::Draw) => "draw".to_string(),
+                        Some(Proof::Loss) => "loss".to_string(),
+                        None => format!("{:.1}%", info.win_rate * 100.0),
+                    };
+                    let pv = info.pv.iter().take(6).map(|m| m.to_string());
+                    lines.push(format!(
+                        "{:4} {:>8} {:>6}  {}",
+                        info.m.to_string(),
+                        info.visits,
+                        result,
+                        pv.collect::<Vec<_>>().join(" ")
+                    ));
+                }
+            }
+            _ => lines.push("search: waiting".to_string()),
+        }
+        while lines.len() < 10 {
+            lines.push(String::new());
+        }
+
+        lines.push("moves:".to_string());
+        let start = self.game.pieces.len().saturating_sub(10) / 2 * 2;
+        for (i, pair) in self.game.pieces[start..].chunks(2).enumerate() {
+            let number = start / 2 + i + 1;
+            let pair = pair.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+            lines.push(format!("{number:3}. {}", pair.join(" ")));
+        }
+        // Above the status line.
+        while lines.len() < 17 {
+            lines.push(String::new());
+        }
+        lines
+    }
+}
+
+fn main() -> ExitCode {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) if args.help => {
+            println!("usage: tui [options]\n{OPTIONS}");
+            return ExitCode::SUCCESS;
+        }
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{message}\nusage: tui [options]\n{OPTIONS}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let game = match &args.load {
+        Some(path) => match record::load(path) {
+            Ok(state) => args.apply_rule(state),
+            Err(err) => {
+                eprintln!("failed to load {path}: {err}");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => args.apply_rule(State::default()),
+    };
+
+    let mut engine = MonteCarloTreeSearchContinue::new(args.mcts_options());
+    engine.set_timeout(args.time);
+    engine.start_simulating();
+    engine.change_cur_state(&game);
+    let engine = EngineHandle::spawn(engine);
+
+    let mut app = App {
+        view: game.pieces.len(),
+        game,
+        players: args.players,
+        args,
+        cursor: (7, 7),
+        thinking: None,
+        analysis: None,
+        ended: None,
+        message: String::new(),
+    };
+    let result = run(&mut app, &engine);
+    match result {
+        Ok(()) => {
+            println!("moves:{:?}", app.game.pieces);
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("terminal error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(app: &mut App, engine: &EngineHandle) -> io::Result<()> {
+    let _terminal = RawTerminal::enter()?;
+    let mut out = io::stdout();
+    execute!(out, terminal::Clear(ClearType::All))?;
+    loop {
+        if let Some(analysis) = engine.analysis.try_iter().last() {
+            app.analysis = Some(analysis);
+        }
+        for (state, m, resigns) in engine.replies.try_iter() {
+            if app.thinking.as_ref().map(|s| &s.pieces) != Some(&state.pieces) {
+                continue;
+            }
+            app.thinking = None;
+            if resigns {
+                let winner = app.game.player_just_moved();
+                app.ended = Some(GameResult::win(winner, Termination::Resignation));
+                continue;
+            }
+            match m {
+                Some(m) if !app.game.pieces.contains(&m) => {
+                    app.play(m);
+                    engine.set_position(&app.game);
+                }
+                _ => {
+                    // Let the human take over rather than asking again.
+                    let to_move = app.game.player_to_move();
+                    app.players[(to_move == Stone::White) as usize] = Player::Human;
+                    app.message = format!("the engine found no move, you play {to_move:?}");
+                }
+            }
+        }
+        let to_move = app.game.player_to_move();
+        if app.thinking.is_none() && !app.is_over() && app.player(to_move) == Player::Engine {
+            app.thinking = Some(app.game.clone());
+            engine.think(&app.game);
+        }
+
+        app.draw(&mut out)?;
+        if !event::poll(Duration::from_millis(100))? {
+            continue;
+        }
+        let quit = match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => !app.handle_key(key, engine),
+            Event::Resize(..) => {
+                execute!(out, terminal::Clear(ClearType::All))?;
+                false
+            }
+            _ => false,
+        };
+        if quit {
+            return Ok(());
+        }
+    }
+}
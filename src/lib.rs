@@ -0,0 +1,3 @@
+//! The parts shared by the binaries.
+
+pub mod args;
@@ -1,74 +1,258 @@
 use std::io::Write;
+use std::process::ExitCode;
+use std::thread;
+use std::time::Duration;
 
+use gomoku::args::{takeback_len, Args, Player, OPTIONS};
 use gomoku_core::{
-    gomoku::{Gomoku, Move, State},
+    game_result::{GameResult, Termination},
+    gomoku::{Gomoku, Move, State, Stone},
     interface::Game,
-    mcts::algorithm::{MCTSOptions, MonteCarloTreeSearchContinue},
+    mcts::algorithm::MonteCarloTreeSearchContinue,
+    record,
 };
 
-fn human_play(game_state: &mut State) -> Move {
-    // Human
+const HELP: &str = "commands:
+  h7, H 7        play a move, column then row
+  undo           take back your last move
+  hint           show the engine's best move for you
+  analyze        show the engine's top moves and their lines
+  swap           switch sides with the engine
+  draw           offer a draw, the engine accepts unless it expects to win
+  resign         give up the game
+  save <file>    save the game (.txt, .psq or .sgf)
+  load <file>    load a game saved by save
+  help           show this message
+  quit           leave without finishing the game";
+
+// How long hint and analyze let the background search run on the position.
+const THINK_TIME: Duration = Duration::from_secs(1);
+
+/// A line of human input.
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    Play(Move),
+    Undo,
+    Hint,
+    Analyze,
+    Swap,
+    Draw,
+    Resign,
+    Save(String),
+    Load(String),
+    Help,
+    Quit,
+}
+
+fn parse_command(input: &str) -> Result<Command, String> {
+    let input = input.trim();
+    let (word, argument) = match input.split_once(char::is_whitespace) {
+        Some((word, argument)) => (word, argument.trim()),
+        None => (input, ""),
+    };
+    let path = || match argument {
+        "" => Err(format!("{word} needs a file name")),
+        path => Ok(path.to_string()),
+    };
+    let command = match word.to_lowercase().as_str() {
+        "" => return Err("enter a move or a command, e.g. \"h7\" or \"help\"".to_string()),
+        "undo" => Command::Undo,
+        "hint" => Command::Hint,
+        "analyze" => Command::Analyze,
+        "swap" => Command::Swap,
+        "draw" => Command::Draw,
+        "resign" => Command::Resign,
+        "save" => return path().map(Command::Save),
+        "load" => return path().map(Command::Load),
+        "help" => Command::Help,
+        "quit" => Command::Quit,
+        _ => {
+            return input
+                .parse()
+                .map(Command::Play)
+                .map_err(|err| format!("{err}, enter \"help\" for the commands"))
+        }
+    };
+    if !argument.is_empty() {
+        return Err(format!("{word} doesn't take an argument"));
+    }
+    Ok(command)
+}
+
+// Read a command of the player of `stone` until it is valid. None at the end
+// of the input.
+fn read_command(stone: Stone) -> Option<Command> {
     let mut input = String::new();
-    print!("enter a move (e.g. \"h 7\"): ");
-    std::io::stdout().flush().unwrap();
     loop {
-        std::io::stdin()
-            .read_line(&mut input)
-            .expect("Failed to read line");
-        match input.trim().split_once(' ').map(|(c, r)| {
-            (
-                r.trim().parse::<usize>().ok(),
-                c.trim()
-                    .to_lowercase()
-                    .bytes()
-                    .next()
-                    .map(|c| (c - b'a') as usize),
-            )
-        }) {
-            Some((Some(row), Some(col))) if !game_state.pieces.contains(&Move::new(row, col)) => {
-                return Move::new(row, col);
-            }
-            _ => {
-                println!("error input, re enter:");
-                input.clear();
-                continue;
-            }
+        print!("{stone:?} to move, enter a move (e.g. \"h7\") or \"help\": ");
+        std::io::stdout().flush().ok()?;
+        input.clear();
+        if std::io::stdin().read_line(&mut input).ok()? == 0 {
+            return None;
+        }
+        match parse_command(&input) {
+            Ok(command) => return Some(command),
+            Err(message) => println!("{message}"),
         }
     }
 }
 
-fn main() {
-    let mut game_state = State::new(vec![]);
-    let option = MCTSOptions::default()
-        .with_max_rollout_depth(225)
-        .with_rollouts_before_expanding(10)
-        .verbose();
-    let mut strategy = MonteCarloTreeSearchContinue::new(option);
+fn main() -> ExitCode {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) if args.help => {
+            println!("usage: gomoku [options]\n{OPTIONS}");
+            return ExitCode::SUCCESS;
+        }
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{message}\nusage: gomoku [options]\n{OPTIONS}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut game_state = match &args.load {
+        Some(path) => match record::load(path) {
+            Ok(state) => args.apply_rule(state),
+            Err(err) => {
+                eprintln!("failed to load {path}: {err}");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => args.apply_rule(State::default()),
+    };
+    let mut strategy = MonteCarloTreeSearchContinue::new(args.mcts_options());
+    strategy.set_timeout(args.time);
     strategy.start_simulating();
+    strategy.change_cur_state(&game_state);
+    // Indexed by the color, black first.
+    let mut players = args.players;
+    let player = |players: &[Player; 2], stone| players[(stone == Stone::White) as usize];
 
     println!("{}", game_state);
-    let _ = loop {
-        // AI
-        let best_move = strategy.choose_move(&game_state).unwrap();
-        println!("best move: {}", best_move);
-        Gomoku::apply(&mut game_state, &best_move);
-        strategy.change_cur_state(&game_state);
-        println!("{}", game_state);
-        if Gomoku::get_winner(&game_state).is_some() {
-            break Gomoku::get_winner(&game_state);
+    // None when the game was left unfinished.
+    let result = loop {
+        if let Some(result) = GameResult::adjudicate(&game_state, args.draw_after) {
+            break Some(result);
         }
-        // human
-        let player_move = human_play(&mut game_state);
-        Gomoku::apply(&mut game_state, &player_move);
-        println!("{}", game_state);
-        if Gomoku::get_winner(&game_state).is_some() {
-            break Gomoku::get_winner(&game_state);
+
+        let to_move = game_state.player_to_move();
+        if player(&players, to_move) == Player::Engine {
+            let Some(best_move) = strategy.choose_move(&game_state) else {
+                println!("the engine found no move");
+                break None;
+            };
+            if strategy.resigns() {
+                break Some(GameResult::win(
+                    to_move.opponent(),
+                    Termination::Resignation,
+                ));
+            }
+            println!("best move: {}", best_move);
+            Gomoku::apply(&mut game_state, &best_move);
+            strategy.change_cur_state(&game_state);
+            println!("{}", game_state);
+            continue;
         }
-    }
-    .unwrap();
 
-    println!("Player{:?} Win the game!", game_state.player_just_moved());
+        let Some(command) = read_command(to_move) else {
+            break None;
+        };
+        match command {
+            Command::Play(m) if game_state.pieces.contains(&m) => {
+                println!("{m} is already taken");
+            }
+            Command::Play(m) => {
+                Gomoku::apply(&mut game_state, &m);
+                strategy.change_cur_state(&game_state);
+                println!("{}", game_state);
+            }
+            Command::Undo => {
+                // Take back the engine's replies too, so it is still our turn.
+                let moves = takeback_len(&game_state, &players);
+                if moves == 0 {
+                    println!("nothing to undo");
+                    continue;
+                }
+                let undone = game_state.pieces[game_state.pieces.len() - moves..].to_vec();
+                game_state = strategy.take_back(moves);
+                println!("{}", game_state);
+                let undone = undone.iter().map(|m| m.to_string());
+                println!("took back {}", undone.collect::<Vec<_>>().join(" "));
+            }
+            Command::Hint => {
+                thread::sleep(THINK_TIME);
+                match strategy.multi_pv(1).first() {
+                    Some(info) => {
+                        println!("hint: {} (win rate {:.1}%)", info.m, info.win_rate * 100.0)
+                    }
+                    None => println!("no hint yet, try again in a moment"),
+                }
+            }
+            Command::Analyze => {
+                thread::sleep(THINK_TIME);
+                let moves = strategy.multi_pv(5);
+                if moves.is_empty() {
+                    println!("nothing analysed yet, try again in a moment");
+                }
+                for info in moves {
+                    let pv = info.pv.iter().map(|m| m.to_string());
+                    println!(
+                        "{:4} visits {:7} win rate {:5.1}%  {}",
+                        info.m.to_string(),
+                        info.visits,
+                        info.win_rate * 100.0,
+                        pv.collect::<Vec<_>>().join(" ")
+                    );
+                }
+            }
+            Command::Swap => {
+                players.swap(0, 1);
+                println!(
+                    "{to_move:?} is now played by the {:?}",
+                    player(&players, to_move)
+                );
+            }
+            Command::Draw => {
+                let engine = to_move.opponent();
+                if player(&players, engine) == Player::Engine {
+                    thread::sleep(THINK_TIME);
+                    if !strategy.accepts_draw(engine) {
+                        println!("the engine declines the draw");
+                        continue;
+                    }
+                }
+                break Some(GameResult::draw(Termination::Agreement));
+            }
+            Command::Resign => {
+                break Some(GameResult::win(
+                    to_move.opponent(),
+                    Termination::Resignation,
+                ));
+            }
+            Command::Save(path) => match record::save(&path, &game_state) {
+                Ok(()) => println!("saved to {path}"),
+                Err(err) => println!("failed to save {path}: {err}"),
+            },
+            Command::Load(path) => match record::load(&path) {
+                Ok(state) => {
+                    game_state = args.apply_rule(state);
+                    strategy.change_cur_state(&game_state);
+                    println!("{}", game_state);
+                }
+                Err(err) => println!("failed to load {path}: {err}"),
+            },
+            Command::Help => println!("{HELP}"),
+            Command::Quit => break None,
+        }
+    };
+
+    if let Some(result) = result {
+        println!("{result}");
+        if let Some(line) = game_state.winning_line() {
+            println!("{line}");
+        }
+    }
     println!("moves:{:?}", game_state.pieces);
+    ExitCode::SUCCESS
 }
 
 #[cfg(test)]
@@ -91,4 +275,21 @@ mod test {
         println!("{}", test_state);
         assert_eq!(test_state.get_winner(), None);
     }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("h7"), Ok(Command::Play(Move::new(7, 7))));
+        assert_eq!(parse_command(" H 7\n"), Ok(Command::Play(Move::new(7, 7))));
+        assert_eq!(parse_command("UNDO"), Ok(Command::Undo));
+        assert_eq!(parse_command("draw"), Ok(Command::Draw));
+        assert_eq!(
+            parse_command("save game 1.sgf"),
+            Ok(Command::Save("game 1.sgf".to_string()))
+        );
+        assert!(parse_command("save").is_err());
+        assert!(parse_command("hint me").is_err());
+        for input in ["", "7h", "z7", "h15", "#", "h-1", "é7"] {
+            assert!(parse_command(input).is_err(), "{input}");
+        }
+    }
 }